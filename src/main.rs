@@ -5,8 +5,8 @@ mod rustnes;
 fn main() {
 
     let options = eframe::NativeOptions {
-        initial_window_size: Some(egui::vec2(500.0, 550.0)),
-        min_window_size: Some(egui::vec2(500.0, 550.0)),
+        initial_window_size: Some(egui::vec2(800.0, 620.0)),
+        min_window_size: Some(egui::vec2(500.0, 620.0)),
         ..Default::default()
     };
     eframe::run_native(
@@ -24,12 +24,26 @@ impl eframe::App for rustnes::RustNES {
 
             self.navigation_bar(ui);
             
-            self.note_stepper(ui);
+            match self.view {
+                rustnes::EditorView::PianoRoll => {
+                    self.note_stepper(ui);
+                    self.volume_lane(ui);
+                },
+                rustnes::EditorView::Tracker => self.tracker_view(ui),
+            }
 
-            self.channel_selector(ui);
+            self.order_editor(ui);
 
             self.control_bar(ui);
 
         });
+
+        self.song_properties(ctx);
+        self.groove_editor(ctx);
+        self.instrument_editor(ctx);
+
+        self.note_entry(ctx);
+        self.clipboard_shortcuts(ctx);
+        self.update_history(ctx);
     }
 }
\ No newline at end of file
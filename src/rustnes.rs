@@ -1,53 +1,197 @@
-use egui::{Color32, Widget, Sense};
+use egui::{Color32, Widget, Sense, Key, Modifiers};
 
 // Moved waves below rustnes to allow mod to play waves, and clear up main
 mod waves;
 
 mod synth;
 mod filters;
+mod timing;
+mod instrument;
+mod instrument_editor;
+mod effect;
+mod export;
+mod history;
+mod clipboard;
+mod tracker_view;
+
+use effect::{Effect, EffectCommand};
+use clipboard::{Selection, Clip};
+pub use tracker_view::EditorView;
+
+// How many pixels a note is dragged to change its volume by one step
+const VOLUME_DRAG_STEP: f32 = 6.0;
+const VOLUME_LANE_HEIGHT: f32 = 60.0;
+
+// How far the mouse wheel scrolls to move the stepper up or down by one row
+const ROW_SCROLL_STEP: f32 = 20.0;
+
+// The width of the piano keyboard on the left of the stepper
+const KEYBOARD_WIDTH: f32 = 36.0;
+
+// The computer keys that enter notes in step entry, laid out like a piano as in FamiTracker.
+// Each key is paired with its semitone above the C of the entry octave
+const NOTE_KEYS: [(Key, u32); 29] = [
+    (Key::Z, 0), (Key::S, 1), (Key::X, 2), (Key::D, 3), (Key::C, 4), (Key::V, 5), (Key::G, 6),
+    (Key::B, 7), (Key::H, 8), (Key::N, 9), (Key::J, 10), (Key::M, 11),
+    (Key::Q, 12), (Key::Num2, 13), (Key::W, 14), (Key::Num3, 15), (Key::E, 16), (Key::R, 17), (Key::Num5, 18),
+    (Key::T, 19), (Key::Num6, 20), (Key::Y, 21), (Key::Num7, 22), (Key::U, 23),
+    (Key::I, 24), (Key::Num9, 25), (Key::O, 26), (Key::Num0, 27), (Key::P, 28),
+];
+
+// The names of the channels, used in hover text
+const CHANNEL_NAMES: [&str; 4] = ["Pulse 1", "Pulse 2", "Triangle", "Noise"];
+
+// The highest octave notes can be entered in
+const MAX_ENTRY_OCTAVE: u32 = 7;
+
+/// A change to the pages of the song asked for from the order editor. It is made once the
+/// whole order editor has been drawn, so no page is removed while it is being drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PageEdit{
+    /// Removes the page and every page after it
+    RemoveFrom(usize),
+    /// Inserts an empty page before the page
+    InsertBefore(usize),
+    /// Removes only the page, moving the pages after it forward
+    Delete(usize),
+}
 
 pub(crate) struct RustNES {
     // Test variable for the GUI. Displays currently selected files name
     pub(crate) _picked_path: Option<String>,
     pub(crate) _test_bool: bool,
     pub(crate) synth: synth::Synth,
+    history: history::History,
 
     pub(crate) unselected_color: Color32,
     pub(crate) selected_color: Color32,
     pub(crate) highlight_color: Color32,
+    pub(crate) black_key_color: Color32,
     pub(crate) scrubber_color: Color32,
-
-    pub(crate) row_highlight_interval: u32,
+    pub(crate) hold_color: Color32,
+    pub(crate) release_color: Color32,
+    pub(crate) selection_color: Color32,
+    /// The colour of each channel's ghost notes, when the other channels are shown
+    pub(crate) channel_colors: [Color32; 4],
 
     pub(crate) scrubber_start: usize,
     pub(crate) selected_channel: usize,
     pub(crate) selected_page: usize,
+    /// The note shown on the bottom row of the stepper
+    pub(crate) lowest_row: u32,
+    // How far the stepper has been scrolled since it last moved a row
+    row_scroll: f32,
+    /// The instrument new notes are placed with
+    pub(crate) selected_instrument: Option<u8>,
 
     pub(crate) channel_symbol: [String;4],
 
     pressed: bool,
+    // How far a note has been dragged since its volume last changed
+    volume_drag: f32,
+
+    /// In select mode, dragging over the stepper selects a rectangle instead of placing notes
+    pub(crate) selecting: bool,
+    /// If selections include every channel, rather than only the selected one
+    pub(crate) select_all_channels: bool,
+    pub(crate) selection: Option<Selection>,
+    // The corner a selection is being dragged from, as (column, row)
+    selection_anchor: Option<(usize, u32)>,
+    clipboard: Option<Clip>,
+    // The page being dragged to a new place in the order editor
+    page_drag: Option<usize>,
+    // A change to the pages waiting for the order editor to finish drawing
+    page_edit: Option<PageEdit>,
+
+    /// How many columns the scrubber menu inserts or deletes
+    pub(crate) column_edit_amount: usize,
+    /// If columns are inserted and deleted in every channel, rather than only the selected one
+    pub(crate) column_edit_all_channels: bool,
+
+    /// In step entry, the computer keyboard enters notes at the scrubber
+    pub(crate) step_entry: bool,
+    /// How many columns the scrubber moves after a note is entered
+    pub(crate) entry_step: usize,
+    /// The octave of the bottom row of keys in step entry
+    pub(crate) entry_octave: u32,
+
+    /// The editor shown in the middle of the window
+    pub(crate) view: EditorView,
+    /// If the stepper shows the notes of the other channels behind the selected one
+    pub(crate) show_other_channels: bool,
+    /// If WAV export leaves out muted channels
+    pub(crate) export_with_mix: bool,
+
+    pub(crate) show_song_properties: bool,
+    pub(crate) show_groove_editor: bool,
+
+    pub(crate) show_instrument_editor: bool,
+    pub(crate) edited_sequence: instrument::SequenceKind,
+    pub(crate) preview_row: u32,
 }
 
 impl Default for RustNES {
     fn default() -> Self {
+        let synth = synth::Synth::default();
+        let history = history::History::new(&synth.project);
+
         Self {
             _picked_path: None,
             _test_bool: false,
-            synth: synth::Synth::new(8, 4, 8),
+            synth,
+            history,
             unselected_color: Color32::from_rgb(100, 100, 100),
             selected_color: Color32::from_rgb(80, 200, 80),
             highlight_color: Color32::from_rgb(60, 80, 60),
+            black_key_color: Color32::from_rgb(75, 75, 75),
             scrubber_color: Color32::from_rgb(60, 120, 60),
-
-            row_highlight_interval: 8,
+            hold_color: Color32::from_rgb(50, 140, 50),
+            release_color: Color32::from_rgb(120, 70, 70),
+            selection_color: Color32::from_rgb(70, 90, 140),
+            channel_colors: [
+                Color32::from_rgb(220, 140, 60),
+                Color32::from_rgb(80, 160, 230),
+                Color32::from_rgb(210, 90, 210),
+                Color32::from_rgb(220, 210, 90),
+            ],
 
             scrubber_start: 0,
             selected_channel: 0,
             selected_page: 0,
+            lowest_row: 36,
+            row_scroll: 0.0,
+            selected_instrument: None,
 
             channel_symbol: ["∏".to_owned(),"∏".to_owned(),"⏶".to_owned(),"♒".to_owned()],
 
             pressed: false,
+            volume_drag: 0.0,
+
+            selecting: false,
+            select_all_channels: false,
+            selection: None,
+            selection_anchor: None,
+            clipboard: None,
+            page_drag: None,
+            page_edit: None,
+
+            column_edit_amount: 1,
+            column_edit_all_channels: true,
+
+            step_entry: false,
+            entry_step: 1,
+            entry_octave: 4,
+
+            view: EditorView::PianoRoll,
+            show_other_channels: false,
+            export_with_mix: false,
+
+            show_song_properties: false,
+            show_groove_editor: false,
+
+            show_instrument_editor: false,
+            edited_sequence: instrument::SequenceKind::Volume,
+            preview_row: 48,
         }
     }
 }
@@ -61,23 +205,46 @@ impl RustNES{
         .show_inside(ui, |ui| {
             ui.horizontal_centered(|ui| {
                 ui.menu_button("File", |ui|{self.file_menu(ui)});
-                ui.menu_button("Edit", Self::edit_menu);
+                ui.menu_button("Edit", |ui|{self.edit_menu(ui)});
+                ui.menu_button("Song", |ui|{self.song_menu(ui)});
+                ui.menu_button("View", |ui|{self.view_menu(ui)});
     
                 #[cfg(debug_assertions)]
                 ui.menu_button("Debug", Self::debug_menu);
+
+                ui.separator();
+                self.song_selector(ui);
             });
         });
     }
 
+    /// The View menu, which switches between the piano roll and tracker editors
+    pub(crate) fn view_menu(&mut self, ui: &mut egui::Ui) {
+        if ui.radio_value(&mut self.view, EditorView::PianoRoll, "Piano Roll").clicked() {
+            ui.close_menu();
+        }
+        if ui.radio_value(&mut self.view, EditorView::Tracker, "Tracker").clicked() {
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        ui.checkbox(&mut self.show_other_channels, "Show Other Channels")
+            .on_hover_text("Shows the notes of the other channels faintly behind the selected one");
+    }
+
     /// The File context menu
-    /// Contains New, Open File, Save, Export (MIDI/NSF), Import (MIDI/NSF)
+    /// Contains New, Open File, Save, Export (WAV/MIDI/NSF/VGM), Import (MIDI/NSF)
     pub(crate) fn file_menu(&mut self, ui: &mut egui::Ui) {
         if ui.button("New").clicked() {
 
             self.selected_page = 0;
+            self.scrubber_start = 0;
 
             //Deletes the old track, and creates a new one
-            self.synth.new_track();
+            self.synth.new_project();
+            self.clamp_selected_instrument();
+            self.history.reset(&self.synth.project);
             ui.close_menu();
         }
 
@@ -89,7 +256,12 @@ impl RustNES{
                 .pick_file() {
 
                 // TODO handle result
-                self.synth.load_track(path).ok();
+                if self.synth.load_project(path).is_ok() {
+                    self.selected_page = 0;
+                    self.scrubber_start = 0;
+                    self.clamp_selected_instrument();
+                    self.history.reset(&self.synth.project);
+                }
             }
             ui.close_menu();
         }
@@ -102,20 +274,60 @@ impl RustNES{
             .save_file() {
 
                 // TODO handle result
-                self.synth.save_track(path).ok();
+                self.synth.save_project(path).ok();
             }
             ui.close_menu();
         }
 
         ui.menu_button("Export", |ui| {
+            if ui.button("WAV").clicked() {
+                let title = self.synth.track().title.to_owned();
+
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("", &["wav"])
+                    .set_file_name(&format!("{}.wav", title))
+                    .save_file() {
+
+                    if let Err(e) = self.synth.export_wav(path, self.export_with_mix) {
+                        println!("RustNES::file_menu: Couldn't export WAV, {}", e);
+                    }
+                }
+                ui.close_menu();
+            }
             if ui.button("MIDI").clicked() {
                 println!("TODO! export work as MIDI");
                 ui.close_menu();
             }
             if ui.button("NSF").clicked() {
-                println!("TODO! export work as NSF");
+                let title = self.synth.project.songs[0].title.to_owned();
+
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("", &["nsf"])
+                    .set_file_name(&format!("{}.nsf", title))
+                    .save_file() {
+
+                    if let Err(e) = self.synth.export_nsf(path, self.export_with_mix) {
+                        println!("RustNES::file_menu: Couldn't export NSF, {}", e);
+                    }
+                }
                 ui.close_menu();
             }
+            if ui.button("VGM").clicked() {
+                let title = self.synth.track().title.to_owned();
+
+                if let Some(path) = rfd::FileDialog::new()
+                    .add_filter("", &["vgm"])
+                    .set_file_name(&format!("{}.vgm", title))
+                    .save_file() {
+
+                    if let Err(e) = self.synth.export_vgm(path, self.export_with_mix) {
+                        println!("RustNES::file_menu: Couldn't export VGM, {}", e);
+                    }
+                }
+                ui.close_menu();
+            }
+            ui.checkbox(&mut self.export_with_mix, "Apply Mute and Solo")
+                .on_hover_text("Leaves muted channels out of the exported audio");
         });
 
         ui.menu_button("Import", |ui| {
@@ -132,11 +344,441 @@ impl RustNES{
     }
 
     /// The edit context menu
-    pub(crate) fn edit_menu(ui: &mut egui::Ui) {
-        if ui.button("STUB").clicked() {
-            println!("TODO!");
+    pub(crate) fn edit_menu(&mut self, ui: &mut egui::Ui) {
+        if ui.add_enabled(self.history.can_undo(), egui::Button::new("Undo").shortcut_text("Ctrl+Z")).clicked() {
+            self.undo();
+            ui.close_menu();
+        }
+
+        if ui.add_enabled(self.history.can_redo(), egui::Button::new("Redo").shortcut_text("Ctrl+Y")).clicked() {
+            self.redo();
             ui.close_menu();
         }
+
+        ui.separator();
+
+        let has_selection = self.selection.is_some();
+        if ui.add_enabled(has_selection, egui::Button::new("Cut").shortcut_text("Ctrl+X")).clicked() {
+            self.cut_selection(ui.ctx());
+            ui.close_menu();
+        }
+        if ui.add_enabled(has_selection, egui::Button::new("Copy").shortcut_text("Ctrl+C")).clicked() {
+            self.copy_selection(ui.ctx());
+            ui.close_menu();
+        }
+        if ui.add_enabled(self.clipboard.is_some(), egui::Button::new("Paste").shortcut_text("Ctrl+V")).clicked() {
+            if let Some(clip) = self.clipboard.clone() {
+                self.paste(&clip);
+            }
+            ui.close_menu();
+        }
+        if ui.add_enabled(has_selection, egui::Button::new("Delete").shortcut_text("Del")).clicked() {
+            self.delete_selection();
+            ui.close_menu();
+        }
+
+        ui.add_enabled_ui(has_selection, |ui| {
+            ui.menu_button("Selection", |ui| self.selection_menu(ui));
+        });
+
+        ui.separator();
+
+        ui.checkbox(&mut self.selecting, "Select Mode");
+        if ui.checkbox(&mut self.select_all_channels, "Select All Channels").changed() {
+            let channel = self.selected_channel;
+            if let Some(selection) = &mut self.selection {
+                selection.channel = if self.select_all_channels {None} else {Some(channel)};
+            }
+        }
+
+        ui.separator();
+
+        ui.checkbox(&mut self.step_entry, "Step Entry");
+        ui.horizontal(|ui| {
+            ui.label("Step");
+            ui.add(egui::DragValue::new(&mut self.entry_step).clamp_range(0..=64));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Octave");
+            ui.add(egui::DragValue::new(&mut self.entry_octave).clamp_range(0..=MAX_ENTRY_OCTAVE));
+        });
+    }
+
+    /// Enters notes from the computer keyboard in step entry. Two rows of keys play two octaves
+    /// like a piano, starting from Z and Q. Each note is entered at the scrubber on the selected
+    /// channel, and the scrubber moves on by the entry step. 1 enters a release and Delete clears
+    /// the column, - and = change the octave, and the arrow keys move the scrubber
+    pub(crate) fn note_entry(&mut self, ctx: &egui::Context){
+        if !self.step_entry || ctx.memory().focus().is_some() {
+            return;
+        }
+
+        let mut input = ctx.input_mut();
+        let notes: Vec<u32> = NOTE_KEYS.iter()
+            .filter(|(key, _)| input.consume_key(Modifiers::NONE, *key))
+            .filter_map(|(_, semitone)| waves::octave_note(self.entry_octave, *semitone))
+            .collect();
+        let release = input.consume_key(Modifiers::NONE, Key::Num1);
+        // Delete is left for the selection when there is one
+        let clear = self.selection.is_none() && input.consume_key(Modifiers::NONE, Key::Delete);
+        let octave_down = input.consume_key(Modifiers::NONE, Key::Minus);
+        let octave_up = input.consume_key(Modifiers::NONE, Key::PlusEquals);
+        let left = input.consume_key(Modifiers::NONE, Key::ArrowLeft);
+        let right = input.consume_key(Modifiers::NONE, Key::ArrowRight);
+        drop(input);
+
+        if octave_down {
+            self.entry_octave = self.entry_octave.saturating_sub(1);
+        }
+        if octave_up {
+            self.entry_octave = (self.entry_octave + 1).min(MAX_ENTRY_OCTAVE);
+        }
+
+        for note in notes {
+            if let Some(column) = self.synth.get_channel_column(self.scrubber_start, self.selected_channel) {
+                column.remove();
+                column.select(note);
+                column.set_instrument(self.selected_instrument);
+            }
+            self.preview_note(note);
+            self.show_row(note);
+            self.move_scrubber(self.entry_step as isize);
+        }
+
+        if release {
+            if let Some(column) = self.synth.get_channel_column(self.scrubber_start, self.selected_channel) {
+                column.release();
+            }
+            self.move_scrubber(self.entry_step as isize);
+        }
+        if clear {
+            if let Some(column) = self.synth.get_channel_column(self.scrubber_start, self.selected_channel) {
+                *column = synth::WaveColumn::default();
+            }
+            self.move_scrubber(self.entry_step as isize);
+        }
+
+        if left {
+            self.move_scrubber(-1);
+        }
+        if right {
+            self.move_scrubber(1);
+        }
+    }
+
+    /// Moves the scrubber by a number of columns, staying inside the song,
+    /// and opens the page it lands on
+    fn move_scrubber(&mut self, columns: isize){
+        let last_column = self.synth.track().get_length() as isize - 1;
+        self.scrubber_start = (self.scrubber_start as isize + columns).clamp(0, last_column) as usize;
+        self.selected_page = self.scrubber_start / self.synth.get_notes_per_page() as usize;
+    }
+
+    /// The operations that change the notes inside the selection
+    fn selection_menu(&mut self, ui: &mut egui::Ui){
+        let mut selection = match self.selection {
+            Some(selection) => selection,
+            None => return,
+        };
+
+        let row_count = self.synth.get_playable_rows();
+        let track = self.synth.track_mut();
+
+        let transpositions = [("Transpose Up", 1), ("Transpose Down", -1), ("Octave Up", 12), ("Octave Down", -12)];
+        for (label, semitones) in transpositions {
+            if ui.button(label).clicked() {
+                selection.transpose(track, semitones, row_count);
+                ui.close_menu();
+            }
+        }
+
+        ui.separator();
+
+        if ui.button("Reverse").clicked() {
+            selection.reverse(track);
+            ui.close_menu();
+        }
+        if ui.button("Double Length").clicked() {
+            selection.double(track);
+            ui.close_menu();
+        }
+        if ui.button("Halve Length").clicked() {
+            selection.halve(track);
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        if ui.button("Shift Left").clicked() {
+            selection.shift(track, false);
+            ui.close_menu();
+        }
+        if ui.button("Shift Right").clicked() {
+            selection.shift(track, true);
+            ui.close_menu();
+        }
+
+        self.selection = Some(selection);
+    }
+
+    /// The copy, cut, paste, and delete shortcuts. Copy and cut also put the clip on
+    /// the system clipboard as text. Pasting only uses the system clipboard, and text
+    /// that isn't a clip is ignored
+    pub(crate) fn clipboard_shortcuts(&mut self, ctx: &egui::Context){
+        if ctx.memory().focus().is_some() {
+            return;
+        }
+
+        let events = ctx.input().events.clone();
+        for event in events {
+            match event {
+                egui::Event::Copy => self.copy_selection(ctx),
+                egui::Event::Cut => self.cut_selection(ctx),
+                egui::Event::Paste(text) => {
+                    if let Some(clip) = Clip::from_text(&text) {
+                        self.paste(&clip);
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        let mut input = ctx.input_mut();
+        let delete = input.consume_key(Modifiers::NONE, Key::Delete);
+        let escape = input.consume_key(Modifiers::NONE, Key::Escape);
+        drop(input);
+
+        if delete {
+            self.delete_selection();
+        }
+        if escape {
+            self.selection = None;
+        }
+    }
+
+    /// Copies the selection to the internal clipboard, and to the system clipboard as text
+    fn copy_selection(&mut self, ctx: &egui::Context){
+        if let Some(selection) = &self.selection {
+            let clip = Clip::copy(self.synth.track(), selection);
+            ctx.output().copied_text = clip.to_text();
+            self.clipboard = Some(clip);
+        }
+    }
+
+    /// Copies the selection like copy_selection, then deletes it
+    fn cut_selection(&mut self, ctx: &egui::Context){
+        self.copy_selection(ctx);
+        self.delete_selection();
+    }
+
+    /// Pastes a clip at the scrubber
+    fn paste(&mut self, clip: &Clip){
+        let channel = self.selected_channel;
+        let start = self.scrubber_start;
+        clip.paste(self.synth.track_mut(), start, channel);
+    }
+
+    fn delete_selection(&mut self){
+        if let Some(selection) = self.selection {
+            selection.delete(self.synth.track_mut());
+        }
+    }
+
+    /// Records the edits made since the last frame as one undoable edit, and handles
+    /// the undo and redo shortcuts. Nothing is recorded while the mouse is held or
+    /// text is being typed, so a whole drag or text edit is undone at once.
+    /// The project only changes from clicks and key presses, so frames where the
+    /// mouse only moved aren't compared against the history
+    pub(crate) fn update_history(&mut self, ctx: &egui::Context){
+        if ctx.input().pointer.any_down() || ctx.memory().focus().is_some() {
+            return;
+        }
+
+        let has_input = ctx.input().events.iter().any(|event| !matches!(event, egui::Event::PointerMoved(_)));
+        if has_input {
+            self.history.checkpoint(&self.synth.project, self.synth.song);
+        }
+
+        let mut input = ctx.input_mut();
+        let undo = input.consume_key(Modifiers::COMMAND, Key::Z);
+        let redo = input.consume_key(Modifiers::COMMAND, Key::Y) || input.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z);
+        drop(input);
+
+        if undo {
+            self.undo();
+        }
+        if redo {
+            self.redo();
+        }
+    }
+
+    fn undo(&mut self){
+        self.history.checkpoint(&self.synth.project, self.synth.song);
+
+        if let Some(song) = self.history.undo(&mut self.synth.project) {
+            self.history_restored(song);
+        }
+    }
+
+    fn redo(&mut self){
+        if let Some(song) = self.history.redo(&mut self.synth.project) {
+            self.history_restored(song);
+        }
+    }
+
+    /// Goes to the song an undone or redone edit was made in, and keeps the
+    /// selected page and scrubber inside it
+    fn history_restored(&mut self, song: usize){
+        self.synth.stop();
+        self.synth.song = song.min(self.synth.project.songs.len() - 1);
+
+        let track = self.synth.track();
+        self.selected_page = self.selected_page.min(track.get_page_count() - 1);
+        self.scrubber_start = self.scrubber_start.min(track.get_length() - 1);
+        self.clamp_selected_instrument();
+    }
+
+    /// The song context menu
+    /// Contains the project's songs, and the editors for song wide settings
+    pub(crate) fn song_menu(&mut self, ui: &mut egui::Ui) {
+        if ui.button("Add Song").clicked() {
+            self.synth.add_song();
+            self.song_changed();
+            ui.close_menu();
+        }
+
+        if ui.add_enabled(self.synth.project.songs.len() > 1, egui::Button::new("Remove Song")).clicked() {
+            self.synth.remove_song();
+            self.song_changed();
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        if ui.button("Properties").clicked() {
+            self.show_song_properties = true;
+            ui.close_menu();
+        }
+
+        if ui.button("Grooves").clicked() {
+            self.show_groove_editor = true;
+            ui.close_menu();
+        }
+
+        if ui.button("Instruments").clicked() {
+            self.show_instrument_editor = true;
+            ui.close_menu();
+        }
+    }
+
+    /// The dropdown in the navigation bar that switches between the project's
+    /// songs, next to the title of the selected song
+    fn song_selector(&mut self, ui: &mut egui::Ui){
+        let songs = &self.synth.project.songs;
+        let mut song = self.synth.song;
+
+        egui::ComboBox::from_id_source("song_selector")
+            .width(140.0)
+            .selected_text(song_name(songs, song))
+            .show_ui(ui, |ui| {
+                for i in 0 .. songs.len() {
+                    ui.selectable_value(&mut song, i, song_name(songs, i));
+                }
+            });
+
+        if song != self.synth.song {
+            self.synth.song = song;
+            self.song_changed();
+        }
+
+        ui.add(egui::TextEdit::singleline(&mut self.synth.track_mut().title).desired_width(120.0));
+    }
+
+    /// Stops playback and goes back to the start, after another song is selected
+    fn song_changed(&mut self){
+        self.synth.stop();
+        self.selected_page = 0;
+        self.scrubber_start = 0;
+    }
+
+    /// The window for the selected song's title, author, copyright, and comments
+    pub(crate) fn song_properties(&mut self, ctx: &egui::Context){
+        let track = self.synth.track_mut();
+
+        egui::Window::new("Song Properties")
+        .open(&mut self.show_song_properties)
+        .show(ctx, |ui| {
+            egui::Grid::new("song_properties_grid").num_columns(2).show(ui, |ui| {
+                ui.label("Title");
+                ui.text_edit_singleline(&mut track.title);
+                ui.end_row();
+
+                ui.label("Author");
+                ui.text_edit_singleline(&mut track.author);
+                ui.end_row();
+
+                ui.label("Copyright");
+                ui.text_edit_singleline(&mut track.copyright);
+                ui.end_row();
+
+                ui.label("Comments");
+                ui.text_edit_multiline(&mut track.comments);
+                ui.end_row();
+            });
+        });
+    }
+
+    /// The window for creating grooves, and choosing the groove the song starts with
+    pub(crate) fn groove_editor(&mut self, ctx: &egui::Context){
+        let track = self.synth.track_mut();
+        let mut removed_groove = None;
+
+        egui::Window::new("Grooves")
+        .open(&mut self.show_groove_editor)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Starting Groove");
+                egui::ComboBox::from_id_source("starting_groove")
+                    .selected_text(track.groove.map_or("None".to_owned(), |groove| groove_name(&track.grooves, groove)))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut track.groove, None, "None");
+                        for i in 0 .. track.grooves.len() {
+                            ui.selectable_value(&mut track.groove, Some(i), groove_name(&track.grooves, i));
+                        }
+                    });
+            });
+
+            ui.separator();
+
+            for (i, groove) in track.grooves.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut groove.name).desired_width(80.0));
+
+                    for frames in &mut groove.frames {
+                        ui.add(egui::DragValue::new(frames).clamp_range(1..=32));
+                    }
+
+                    if ui.small_button("+").clicked() {
+                        let last = groove.frames.last().copied().unwrap_or(6);
+                        groove.frames.push(last);
+                    }
+                    if groove.frames.len() > 1 && ui.small_button("–").clicked() {
+                        groove.frames.pop();
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed_groove = Some(i);
+                    }
+                });
+            }
+
+            if ui.button("Add Groove").clicked() {
+                track.grooves.push(synth::Groove::default());
+            }
+        });
+
+        if let Some(groove) = removed_groove {
+            track.remove_groove(groove);
+        }
     }
 
     /// The debug contect menu.
@@ -171,42 +813,221 @@ impl RustNES{
                     self.synth.stop();
                 }
                 ui.add(egui::Slider::new(&mut self.synth.volume, 0.0..=100.0).show_value(false));
+                ui.toggle_value(&mut self.selecting, "Select");
+                ui.toggle_value(&mut self.step_entry, "Step Entry")
+                    .on_hover_text("Enter notes at the scrubber with the computer keyboard");
+
+                ui.separator();
+                self.octave_selector(ui);
+
+                ui.separator();
+                self.instrument_selector(ui);
+
+                ui.separator();
+                self.song_timing(ui);
             });
         });
     }
 
+    /// Chooses the instrument that new notes are placed with
+    fn instrument_selector(&mut self, ui: &mut egui::Ui){
+        let instruments = &self.synth.project.instruments;
+
+        egui::ComboBox::from_id_source("selected_instrument")
+            .selected_text(self.selected_instrument.map_or("No Instrument".to_owned(), |i| instrument_name(instruments, i as usize)))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.selected_instrument, None, "No Instrument");
+                for i in 0 .. instruments.len() {
+                    ui.selectable_value(&mut self.selected_instrument, Some(i as u8), instrument_name(instruments, i));
+                }
+            });
+    }
+
+    /// The tempo, rows per beat, and time signature of the song
+    /// Changing anything but the tempo lays the song out again across pages of the new size
+    fn song_timing(&mut self, ui: &mut egui::Ui){
+        let track = self.synth.track_mut();
+
+        ui.label("BPM");
+        ui.add(egui::DragValue::new(&mut track.bpm).clamp_range(1..=999));
+
+        ui.label("Speed");
+        ui.add(egui::DragValue::new(&mut track.speed).clamp_range(1..=31));
+
+        egui::ComboBox::from_id_source("timing_mode")
+            .selected_text(match track.timing_mode {
+                timing::TimingMode::RealTime => "Real Time",
+                timing::TimingMode::EngineTick => "Engine Tick",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut track.timing_mode, timing::TimingMode::RealTime, "Real Time");
+                ui.selectable_value(&mut track.timing_mode, timing::TimingMode::EngineTick, "Engine Tick");
+            });
+
+        egui::ComboBox::from_id_source("region")
+            .width(60.0)
+            .selected_text(match track.region {
+                timing::Region::Ntsc => "NTSC",
+                timing::Region::Pal => "PAL",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut track.region, timing::Region::Ntsc, "NTSC");
+                ui.selectable_value(&mut track.region, timing::Region::Pal, "PAL");
+            });
+
+        let (mut rows_per_beat, mut beats_per_measure, mut measures_per_page) = (track.rows_per_beat, track.beats_per_measure, track.measures_per_page);
+
+        ui.label("Rows/Beat");
+        let mut layout_changed = ui.add(egui::DragValue::new(&mut rows_per_beat).clamp_range(1..=16)).changed();
+
+        ui.label("Time");
+        layout_changed |= ui.add(egui::DragValue::new(&mut beats_per_measure).clamp_range(1..=16)).changed();
+        ui.label("/");
+        egui::ComboBox::from_id_source("beat_unit")
+            .width(40.0)
+            .selected_text(track.beat_unit.to_string())
+            .show_ui(ui, |ui| {
+                for unit in [2, 4, 8, 16] {
+                    ui.selectable_value(&mut track.beat_unit, unit, unit.to_string());
+                }
+            });
+
+        ui.label("Measures/Page");
+        layout_changed |= ui.add(egui::DragValue::new(&mut measures_per_page).clamp_range(1..=8)).changed();
+
+        if layout_changed {
+            track.set_page_layout(rows_per_beat, beats_per_measure, measures_per_page);
+            self.scrubber_start = self.scrubber_start.min(track.get_length() - 1);
+            self.selected_page = self.scrubber_start / track.get_rows_per_page() as usize;
+        }
+    }
+
     /// Creates the note stepper in the middle of the UI
     /// Uses the columns ui to create a grid of buttons that each correspond
-    /// to a specific note. Currently only 8 buttons per screen.
+    /// to a specific note. There is one column for each beat on the page
     pub(crate) fn note_stepper(&mut self, ui: &mut egui::Ui){
-        ui.columns(self.synth.measures_per_page as usize, |columns|{
+        let beats_per_page = self.synth.track().get_beats_per_page();
+
+        let stepper = ui.with_layout(egui::Layout::left_to_right(egui::Align::Min), |ui| {
+            self.piano_keyboard(ui);
+
+            ui.vertical(|ui| {
+                ui.columns(beats_per_page as usize, |columns|{
+
+                    for column_index in 0 .. beats_per_page{
+
+                        if column_index as usize >= columns.len(){
+                            println!("RustNES::note_stepper: current_page_index {} out of bounds", column_index);
+                            continue;
+                        }
+
+                        self.stepper_column(&mut columns[column_index as usize], column_index);
+                    }
+                });
+            });
+        });
 
-            for column_index in 0 .. self.synth.measures_per_page{
+        // The mouse wheel scrolls the stepper through the notes of the period table
+        if ui.rect_contains_pointer(stepper.response.rect) {
+            self.row_scroll += ui.input().scroll_delta.y;
+            let steps = (self.row_scroll / ROW_SCROLL_STEP).trunc();
 
-                if column_index as usize >= columns.len(){
-                    println!("RustNES::note_stepper: current_page_index {} out of bounds", column_index);
-                    continue;
+            if steps != 0.0 {
+                self.row_scroll -= steps * ROW_SCROLL_STEP;
+                self.scroll_rows(steps as i32);
+            }
+        }
+    }
+
+    /// The piano keys along the left of the stepper, one for each row, labelled with
+    /// the name of its note. Clicking a key plays its note on the selected channel
+    fn piano_keyboard(&mut self, ui: &mut egui::Ui){
+        let highest_row = self.lowest_row + self.synth.rows_per_column - 1;
+
+        ui.vertical(|ui| {
+            ui.set_width(KEYBOARD_WIDTH);
+            ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
+
+            // Lines the keys up with the rows, below the scrubber buttons
+            ui.add_visible(false, egui::Button::new("").small());
+
+            for row_index in (self.lowest_row ..= highest_row).rev() {
+                let (fill, text) = if waves::is_black_key(row_index) {
+                    (Color32::from_gray(30), Color32::from_gray(220))
                 }
+                else {
+                    (Color32::from_gray(220), Color32::from_gray(30))
+                };
 
-                self.stepper_column(&mut columns[column_index as usize], column_index);
+                let key = egui::Button::new(egui::RichText::new(waves::note_name(row_index)).color(text))
+                    .small()
+                    .fill(fill)
+                    .min_size(egui::vec2(KEYBOARD_WIDTH, 0.0));
+
+                if ui.add(key).clicked() {
+                    self.preview_note(row_index);
+                }
             }
         });
     }
 
+    /// Plays the note of a row on the selected channel, with the selected instrument if there is one
+    fn preview_note(&self, row_index: u32){
+        match self.selected_instrument.and_then(|i| self.synth.project.instruments.get(i as usize)) {
+            Some(instrument) => self.synth.preview_instrument(self.selected_channel, row_index, instrument, 0.2),
+            None => self.synth.play_note(self.selected_channel as u32, row_index as i32),
+        }
+    }
+
+    /// Scrolls the stepper just far enough for a row to be shown
+    fn show_row(&mut self, row_index: u32){
+        let highest_row = self.lowest_row + self.synth.rows_per_column - 1;
+
+        if row_index < self.lowest_row {
+            self.scroll_rows(row_index as i32 - self.lowest_row as i32);
+        }
+        else if row_index > highest_row {
+            self.scroll_rows(row_index as i32 - highest_row as i32);
+        }
+    }
+
+    /// Moves the rows shown in the stepper up or down, keeping them inside the period table
+    fn scroll_rows(&mut self, rows: i32){
+        let highest = self.synth.get_playable_rows().saturating_sub(self.synth.rows_per_column);
+        self.lowest_row = (self.lowest_row as i32 + rows).clamp(0, highest as i32) as u32;
+    }
+
+    /// Buttons that move the stepper up and down by an octave
+    fn octave_selector(&mut self, ui: &mut egui::Ui){
+        let highest = self.synth.get_playable_rows().saturating_sub(self.synth.rows_per_column);
+        let shown = format!("Showing {} to {}", waves::note_name(self.lowest_row), waves::note_name(self.lowest_row + self.synth.rows_per_column - 1));
+
+        ui.label("Octave").on_hover_text(&shown);
+        if ui.add_enabled(self.lowest_row > 0, egui::Button::new("-")).on_hover_text(&shown).clicked() {
+            self.scroll_rows(-12);
+        }
+        if ui.add_enabled(self.lowest_row < highest, egui::Button::new("+")).on_hover_text(&shown).clicked() {
+            self.scroll_rows(12);
+        }
+    }
+
     /// The current column being rendered
     /// Spacing is set to 0,0 so that the notes sit right next to each other
     fn stepper_column(&mut self, ui: &mut egui::Ui, current_column_index: u32){
 
         let first_measure_index = self.selected_page as u32 * self.synth.get_notes_per_page();
+        let rows_per_beat = self.synth.track().rows_per_beat;
         ui.spacing_mut().item_spacing = egui::vec2(0.0, 0.0);
 
-        for row_index in (0 .. self.synth.rows_per_column).rev(){
-            ui.columns(4, |columns|{
-                for j in 0 .. self.synth.notes_per_measure{
+        let highest_row = self.lowest_row + self.synth.rows_per_column - 1;
 
-                    let column_index = first_measure_index + (current_column_index * self.synth.notes_per_measure) + j;
+        for row_index in (self.lowest_row ..= highest_row).rev(){
+            ui.columns(rows_per_beat as usize, |columns|{
+                for j in 0 .. rows_per_beat{
 
-                    if row_index == self.synth.rows_per_column - 1{
+                    let column_index = first_measure_index + (current_column_index * rows_per_beat) + j;
+
+                    if row_index == highest_row{
                         self.scrubber_button(&mut columns[j as usize], column_index);
                     }
                     self.column_button(&mut columns[j as usize], column_index, row_index)
@@ -215,108 +1036,653 @@ impl RustNES{
         }
     }
 
+    /// The button above each column that sets where playback starts.
+    /// Columns with a tempo change are marked with an "F", like a tracker Fxx command,
+    /// followed by the letter of the selected channel's effect
     fn scrubber_button(&mut self, ui: &mut egui::Ui, column_index: u32){
-        if ui.small_button("").clicked(){
+        let tempo_event = self.synth.track().tempo_lane[column_index as usize];
+        let effect = self.synth.get_channel_column(column_index as usize, self.selected_channel).and_then(|column| column.get_effect());
+
+        let mut label = if tempo_event.is_some() {"F".to_owned()} else {String::new()};
+        let mut hover_text = match tempo_event {
+            Some(synth::TempoEvent::Bpm(bpm)) => vec![format!("BPM {}", bpm)],
+            Some(synth::TempoEvent::Speed(speed)) => vec![format!("Speed {}", speed)],
+            Some(synth::TempoEvent::Groove(groove)) => vec![format!("Groove {}", groove_name(&self.synth.track().grooves, groove))],
+            None => vec![],
+        };
+        if let Some(effect) = effect {
+            label.push(effect.command.symbol());
+            hover_text.push(format!("{} {}", effect.command.name(), effect));
+        }
+
+        let mut response = ui.small_button(label);
+        if !hover_text.is_empty() {
+            response = response.on_hover_text(hover_text.join("\n"));
+        }
+
+        if response.clicked(){
             self.scrubber_start = column_index as usize;
         }
+
+        response.context_menu(|ui| {
+            self.tempo_menu(ui, column_index as usize);
+            ui.separator();
+            self.effect_menu(ui, column_index as usize);
+            ui.separator();
+            self.column_menu(ui, column_index as usize);
+        });
+    }
+
+    /// The right click menu of the scrubber, to add or remove a tempo change
+    fn tempo_menu(&mut self, ui: &mut egui::Ui, column_index: usize){
+        let current_bpm = self.synth.track().get_bpm_at(column_index);
+        let current_speed = self.synth.track().get_speed_at(column_index);
+        let track = self.synth.track_mut();
+        let grooves = &track.grooves;
+        let tempo_event = &mut track.tempo_lane[column_index];
+
+        match tempo_event {
+            Some(synth::TempoEvent::Bpm(bpm)) => {
+                ui.horizontal(|ui| {
+                    ui.label("BPM");
+                    ui.add(egui::DragValue::new(bpm).clamp_range(1..=999));
+                });
+            },
+            Some(synth::TempoEvent::Speed(speed)) => {
+                ui.horizontal(|ui| {
+                    ui.label("Speed");
+                    ui.add(egui::DragValue::new(speed).clamp_range(1..=31));
+                });
+            },
+            Some(synth::TempoEvent::Groove(groove)) => {
+                ui.horizontal(|ui| {
+                    ui.label("Groove");
+                    egui::ComboBox::from_id_source("groove_event")
+                        .selected_text(groove_name(grooves, *groove))
+                        .show_ui(ui, |ui| {
+                            for i in 0 .. grooves.len() {
+                                ui.selectable_value(groove, i, groove_name(grooves, i));
+                            }
+                        });
+                });
+            },
+            None => {
+                if ui.button("Add Tempo Change").clicked() {
+                    *tempo_event = Some(synth::TempoEvent::Bpm(current_bpm));
+                }
+                if ui.button("Add Speed Change").clicked() {
+                    *tempo_event = Some(synth::TempoEvent::Speed(current_speed));
+                }
+                if ui.add_enabled(!grooves.is_empty(), egui::Button::new("Add Groove Change")).clicked() {
+                    *tempo_event = Some(synth::TempoEvent::Groove(0));
+                }
+                return;
+            },
+        }
+
+        if ui.button("Remove Tempo Change").clicked() {
+            *tempo_event = None;
+            ui.close_menu();
+        }
+    }
+
+    /// The part of the scrubber menu that adds, edits, or removes the effect
+    /// of the selected channel's column
+    fn effect_menu(&mut self, ui: &mut egui::Ui, column_index: usize){
+        let column = match self.synth.get_channel_column(column_index, self.selected_channel) {
+            Some(column) => column,
+            None => return,
+        };
+
+        let mut effect = match column.get_effect() {
+            Some(effect) => effect,
+            None => {
+                if ui.button("Add Effect").clicked() {
+                    column.set_effect(Some(Effect { command: EffectCommand::Arpeggio, param: 0 }));
+                }
+                return;
+            },
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Effect");
+            egui::ComboBox::from_id_source("effect_command")
+                .selected_text(format!("{} {}", effect.command.symbol(), effect.command.name()))
+                .show_ui(ui, |ui| {
+                    for command in EffectCommand::ALL {
+                        ui.selectable_value(&mut effect.command, command, format!("{} {}", command.symbol(), command.name()));
+                    }
+                });
+            ui.add(egui::DragValue::new(&mut effect.param).hexadecimal(2, false, true));
+        });
+        column.set_effect(Some(effect));
+
+        if ui.button("Remove Effect").clicked() {
+            column.set_effect(None);
+            ui.close_menu();
+        }
+    }
+
+    /// The part of the scrubber menu that inserts or deletes columns, moving
+    /// the rest of the song back or forward
+    fn column_menu(&mut self, ui: &mut egui::Ui, column_index: usize){
+        ui.horizontal(|ui| {
+            ui.label("Columns");
+            ui.add(egui::DragValue::new(&mut self.column_edit_amount).clamp_range(1..=256));
+        });
+        ui.checkbox(&mut self.column_edit_all_channels, "All Channels");
+
+        let channel = if self.column_edit_all_channels {None} else {Some(self.selected_channel)};
+        let amount = self.column_edit_amount;
+
+        ui.horizontal(|ui| {
+            if ui.button("Insert").clicked() {
+                self.synth.track_mut().insert_columns(column_index, amount, channel);
+                ui.close_menu();
+            }
+            if ui.button("Delete").clicked() {
+                self.synth.track_mut().delete_columns(column_index, amount, channel);
+                ui.close_menu();
+            }
+        });
     }
 
     /// The current button being rendered to the column
+    /// Clicking places a note, shift places a held note that continues the
+    /// previous one, and alt releases the previous note.
+    /// Dragging a note up or down with ctrl held changes its volume,
+    /// and ctrl right clicking it clears the volume
     fn column_button(&mut self, ui: &mut egui::Ui, column_index: u32, row_index: u32){
-        let option_curr = self.synth.get_channel_column(column_index as usize, self.selected_channel);
-
-        match option_curr {
+        // A copy of the column, so the colors can be read while deciding what to draw
+        let curr = match self.synth.get_channel_column(column_index as usize, self.selected_channel) {
             None => {
                 println!("RustNES::column_button: option is none");
                 return;
             },
-            Some(curr) =>{
-    
-                let button = egui::Button::new("")
-                    // TODO move this into a function, and fix this mess
-                    .fill(
-                        if curr.is_selected(row_index) {self.selected_color}
-                        else if column_index as usize == self.scrubber_start {self.scrubber_color}
-                        else if row_index % self.row_highlight_interval == 0 {self.highlight_color}
-                        else { self.unselected_color}
-                    )
-                    .small()
-                    .sense(Sense{ click: true, drag: true, focusable: false });
-                let response = button.ui(ui);
-            
-                if response.hovered() && curr.is_selected(row_index) && response.ctx.input().pointer.secondary_down(){
-                    curr.remove();
-                    //println!("{} {} removed", column_index, row_index);
-                    self.pressed = false;
-                    return;
-                } 
-
-                // TODO Fix this mess
-                // If the button is pressed, then select the current note
-                if response.hovered() && !curr.is_selected(row_index) && response.ctx.input().pointer.primary_down() && !self.pressed{
-                    curr.select(row_index);
-                    //println!("{} {} selected", column_index, row_index);
-                    self.synth.play_note(self.selected_channel as u32, row_index as i32 + 36);
+            Some(curr) => curr.clone(),
+        };
+
+        let button = egui::Button::new("")
+            .fill(self.cell_color(&curr, column_index, row_index))
+            .small()
+            .sense(Sense{ click: true, drag: true, focusable: false });
+        let mut response = button.ui(ui);
+        if let (true, Some(volume)) = (curr.is_selected(row_index), curr.get_volume()) {
+            response = response.on_hover_text(format!("Volume {}", volume));
+        }
+
+        let modifiers = response.ctx.input().modifiers;
+
+        if self.selecting {
+            self.select_cells(&response, column_index as usize, row_index);
+            return;
+        }
+
+        let column = self.synth.get_channel_column(column_index as usize, self.selected_channel).unwrap();
+
+        if modifiers.command && curr.is_selected(row_index) {
+            if response.drag_started() {
+                self.volume_drag = 0.0;
+            }
+
+            if response.dragged() {
+                // Up is louder, and notes without a volume start from full volume
+                self.volume_drag -= response.drag_delta().y;
+                let steps = (self.volume_drag / VOLUME_DRAG_STEP).trunc();
+
+                if steps != 0.0 {
+                    self.volume_drag -= steps * VOLUME_DRAG_STEP;
+                    let volume = column.get_volume().unwrap_or(synth::MAX_VOLUME) as i32 + steps as i32;
+                    column.set_volume(Some(volume.clamp(0, synth::MAX_VOLUME as i32) as u8));
                 }
-            
-                // On a drag, select multiple notes
-                else if response.hovered() && response.ctx.input().pointer.primary_clicked(){
-                    curr.select(row_index);
-                    //println!("{} {} clicked", column_index, row_index);
-                    self.pressed = true;
+
+                // Keeps the drag from placing notes on the cells it passes over
+                self.pressed = true;
+            }
+
+            if response.secondary_clicked() {
+                column.set_volume(None);
+            }
+            return;
+        }
+    
+        if response.hovered() && (curr.is_selected(row_index) || curr.is_released()) && response.ctx.input().pointer.secondary_down(){
+            column.remove();
+            //println!("{} {} removed", column_index, row_index);
+            self.pressed = false;
+            return;
+        } 
+
+        // TODO Fix this mess
+        // If the button is pressed, then select the current note
+        if response.hovered() && !curr.is_selected(row_index) && response.ctx.input().pointer.primary_down() && !self.pressed{
+            if modifiers.alt {
+                column.release();
+            }
+            else if modifiers.shift {
+                column.hold(row_index);
+                column.set_instrument(self.selected_instrument);
+            }
+            else {
+                column.select(row_index);
+                column.set_instrument(self.selected_instrument);
+                //println!("{} {} selected", column_index, row_index);
+
+                self.preview_note(row_index);
+            }
+        }
+    
+        // On a drag, select multiple notes
+        else if response.hovered() && response.ctx.input().pointer.primary_clicked() && !modifiers.alt && !modifiers.shift{
+            column.select(row_index);
+            column.set_instrument(self.selected_instrument);
+            //println!("{} {} clicked", column_index, row_index);
+            self.pressed = true;
+        }
+    
+        // To stop the notes from turning on and off, use a flag
+        // Gross
+        if response.ctx.input().pointer.primary_released() {
+            self.pressed = false;
+        }   
+    }
+
+    /// Drags out a rectangle of cells in select mode, from the cell the drag started on
+    fn select_cells(&mut self, response: &egui::Response, column_index: usize, row_index: u32){
+        let pointer = response.ctx.input().pointer.clone();
+
+        if response.hovered() && pointer.any_pressed() && pointer.primary_down() {
+            self.selection_anchor = Some((column_index, row_index));
+        }
+
+        if let (true, true, Some(anchor)) = (response.hovered(), pointer.primary_down(), self.selection_anchor) {
+            let channel = if self.select_all_channels {None} else {Some(self.selected_channel)};
+            self.selection = Some(Selection::new(anchor, (column_index, row_index), channel));
+        }
+
+        if pointer.primary_released() {
+            self.selection_anchor = None;
+        }
+    }
+
+    /// The fill of a note button. Held notes are darker than the notes that
+    /// start them, and columns with a release are tinted red.
+    /// Notes of the other channels are tinted in with their channel's colour
+    fn cell_color(&self, curr: &synth::WaveColumn, column_index: u32, row_index: u32) -> Color32{
+        let background = self.background_color(curr, column_index, row_index);
+
+        match self.ghost_channel(column_index as usize, row_index) {
+            Some(channel) if !curr.is_selected(row_index) => mix_colors(background, self.channel_colors[channel], 0.4),
+            _ => background,
+        }
+    }
+
+    /// The first of the other channels with a note on a cell, if they are being shown
+    fn ghost_channel(&self, column_index: usize, row_index: u32) -> Option<usize>{
+        if !self.show_other_channels {
+            return None;
+        }
+
+        let track = self.synth.track();
+        (0 .. track.get_channel_count())
+            .filter(|channel| *channel != self.selected_channel)
+            .find(|channel| matches!(track.get_column(column_index, *channel), Some(column) if column.is_selected(row_index)))
+    }
+
+    fn background_color(&self, curr: &synth::WaveColumn, column_index: u32, row_index: u32) -> Color32{
+        let in_selection = matches!(self.selection, Some(selection) if selection.contains(column_index as usize, row_index, self.selected_channel));
+
+        if in_selection {
+            if curr.is_selected(row_index) {self.selected_color.linear_multiply(0.6)} else {self.selection_color}
+        }
+        else if curr.is_selected(row_index) {
+            if curr.is_held() {self.hold_color} else {self.selected_color}
+        }
+        else if curr.is_released() {self.release_color}
+        else if column_index as usize == self.scrubber_start {self.scrubber_color}
+        else if waves::is_octave_start(row_index) {self.highlight_color}
+        else if waves::is_black_key(row_index) {self.black_key_color}
+        else {self.unselected_color}
+    }
+
+    /// The lane under the note stepper, with a bar for the volume of each note on
+    /// the selected channel. Drawing over the bars sets the volumes, and right
+    /// dragging clears them. Notes without a volume show the one they inherit
+    pub(crate) fn volume_lane(&mut self, ui: &mut egui::Ui){
+        let beats_per_page = self.synth.track().get_beats_per_page() as usize;
+        let rows_per_beat = self.synth.track().rows_per_beat as usize;
+        let rows_per_page = self.synth.get_notes_per_page() as usize;
+        let first_column = self.selected_page * rows_per_page;
+
+        let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), VOLUME_LANE_HEIGHT), Sense::click_and_drag());
+
+        // The lane starts after the space the piano keyboard takes up beside the stepper
+        let spacing = ui.spacing().item_spacing.x;
+        let rect = response.rect;
+        let rect = egui::Rect::from_min_max(egui::pos2(rect.left() + KEYBOARD_WIDTH + spacing, rect.top()), rect.max);
+        let width = rect.width();
+
+        painter.rect_filled(rect, 0.0, Color32::from_rgb(30, 30, 30));
+
+        // The same widths ui.columns gives the note stepper, so each bar sits under its column
+        let beat_width = (width - spacing * (beats_per_page - 1) as f32) / beats_per_page as f32;
+        let column_width = beat_width / rows_per_beat as f32;
+        let column_left = |i: usize| rect.left() + (i / rows_per_beat) as f32 * (beat_width + spacing) + (i % rows_per_beat) as f32 * column_width;
+
+        if let Some(pos) = response.interact_pointer_pos() {
+            let beat = ((pos.x - rect.left()) / (beat_width + spacing)) as usize;
+            let row = ((pos.x - column_left(beat * rows_per_beat)) / column_width) as usize;
+
+            // Nothing is drawn in the gaps between beats
+            if rect.contains(pos) && beat < beats_per_page && row < rows_per_beat {
+                let volume = ((rect.bottom() - pos.y) / rect.height() * synth::MAX_VOLUME as f32).round() as u8;
+                let clear = ui.input().pointer.secondary_down();
+
+                if let Some(column) = self.synth.get_channel_column(first_column + beat * rows_per_beat + row, self.selected_channel) {
+                    if matches!(column.get_event(), synth::NoteEvent::On | synth::NoteEvent::Hold) {
+                        column.set_volume(if clear {None} else {Some(volume)});
+                    }
                 }
-            
-                // To stop the notes from turning on and off, use a flag
-                // Gross
-                if response.ctx.input().pointer.primary_released() {
-                    self.pressed = false;
-                }   
-            },
+            }
+        }
+
+        let channel = self.synth.track().channel_columns(self.selected_channel);
+        let page = match channel.get(first_column .. first_column + rows_per_page) {
+            Some(page) => page,
+            None => return,
+        };
+
+        let mut volume = channel[.. first_column].iter().rev().find_map(|column| column.get_volume()).unwrap_or(synth::MAX_VOLUME);
+
+        for (i, column) in page.iter().enumerate() {
+            if let Some(column_volume) = column.get_volume() {
+                volume = column_volume;
+            }
+
+            if !matches!(column.get_event(), synth::NoteEvent::On | synth::NoteEvent::Hold) {
+                continue;
+            }
+
+            let color = match column.get_volume() {
+                Some(_) if column.is_held() => self.hold_color,
+                Some(_) => self.selected_color,
+                None => self.unselected_color,
+            };
+
+            let left = column_left(i);
+            let top = rect.bottom() - volume as f32 / synth::MAX_VOLUME as f32 * rect.height();
+            let bar = egui::Rect::from_min_max(egui::pos2(left + 1.0, top), egui::pos2(left + column_width - 1.0, rect.bottom()));
+
+            painter.rect_filled(bar, 0.0, color);
         }
-            
     }
 
-    /// The channel selector to be able to select which of the 4 main channels are being used.
-    /// The default channel is currently PulseOne
-    pub(crate) fn channel_selector(&mut self, ui: &mut egui::Ui){
+    /// The order editor along the bottom of the window, which replaced the channel selector.
+    /// Each page of the song is a column holding the pattern each channel plays on it.
+    /// Clicking a pattern opens it in the note stepper
+    pub(crate) fn order_editor(&mut self, ui: &mut egui::Ui){
         ui.separator();
 
         egui::ScrollArea::horizontal().show(ui, |ui| {
-            egui::Grid::new("channel_grid").show(ui, |ui| {
-                for i in 0..self.synth.track.page_count as usize{
-                    ui.vertical_centered_justified(|vertical|{
-                        self.subtract_channel_columns(vertical, i);
+            egui::Grid::new("order_grid").show(ui, |ui| {
+                ui.vertical_centered_justified(|vertical|{
+                    self.channel_mix(vertical);
+                });
+
+                let mut page_rects = Vec::new();
+                for i in 0..self.synth.track().get_page_count(){
+                    let column = ui.vertical_centered_justified(|vertical|{
+                        self.order_column(vertical, i);
                     });
+                    page_rects.push(column.response.rect);
                 }
                 self.add_channel_columns(ui);
+
+                self.drop_page(ui, &page_rects);
             });
         });
+
+        self.apply_page_edit();
     }
 
-    /// Adds the channel controls and allows selecting specific channels and measure
-    fn subtract_channel_columns(&mut self, ui: &mut egui::Ui, measure_index: usize){
-        // If the index is less than current measure, then add the channel column
-            if ui.button("–").clicked() {
-                let remove_amount = self.synth.track.page_count as usize - measure_index;
+    /// Makes the change to the pages asked for while the order editor was drawn
+    fn apply_page_edit(&mut self){
+        match self.page_edit.take() {
+            Some(PageEdit::RemoveFrom(page_index)) => {
+                let remove_amount = self.synth.track().get_page_count().saturating_sub(page_index);
                 if self.synth.remove_page(remove_amount) {
-                    self.selected_page = self.synth.track.page_count as usize - 1;
+                    self.selected_page = self.synth.track().get_page_count() - 1;
+                    self.scrubber_start = self.scrubber_start.min(self.synth.track().get_length() - 1);
+                }
+            },
+            Some(PageEdit::InsertBefore(page_index)) => {
+                self.synth.track_mut().insert_page(page_index);
+                self.follow_pages(|page| if page >= page_index {page + 1} else {page});
+            },
+            Some(PageEdit::Delete(page_index)) => {
+                if self.synth.track().get_page_count() <= 1 {
+                    return;
+                }
+                self.synth.track_mut().delete_page(page_index);
+
+                // Anything on the deleted page moves to the page that took its place
+                self.follow_pages(|page| if page > page_index {page - 1} else {page});
+                self.selected_page = self.selected_page.min(self.synth.track().get_page_count() - 1);
+                self.scrubber_start = self.scrubber_start.min(self.synth.track().get_length() - 1);
+            },
+            None => (),
+        }
+    }
+
+    /// The mute and solo buttons of each channel, at the start of the order editor.
+    /// They change what is heard straight away, even while a song is playing
+    fn channel_mix(&mut self, ui: &mut egui::Ui){
+        // Lines the buttons up with the patterns, below the page numbers and remove buttons
+        ui.label("");
+        ui.add_visible(false, egui::Button::new("–"));
+
+        let mix = &self.synth.mix;
+        for (channel, name) in CHANNEL_NAMES.iter().enumerate() {
+            ui.horizontal(|ui| {
+                let muted = mix.is_muted(channel);
+                let mute = egui::Button::new("M").fill(if muted {self.release_color} else {self.unselected_color});
+                if ui.add(mute).on_hover_text(format!("Mute {}", name)).clicked() {
+                    mix.set_muted(channel, !muted);
+                }
+
+                let soloed = mix.is_soloed(channel);
+                let solo = egui::Button::new("S").fill(if soloed {self.selected_color} else {self.unselected_color});
+                if ui.add(solo).on_hover_text(format!("Solo {}", name)).clicked() {
+                    mix.set_soloed(channel, !soloed);
                 }
+            });
+        }
+    }
+
+    /// Moves the page being dragged to the page under the pointer once it is let go,
+    /// or swaps the two pages if shift is held. The place it will land is outlined while dragging
+    fn drop_page(&mut self, ui: &mut egui::Ui, page_rects: &[egui::Rect]){
+        let from = match self.page_drag {
+            Some(from) => from,
+            None => return,
+        };
+
+        let (pointer, released, swap) = {
+            let input = ui.input();
+            (input.pointer.hover_pos(), !input.pointer.any_down(), input.modifiers.shift)
+        };
+
+        let target = pointer.and_then(|pointer| page_rects.iter().position(|rect| rect.x_range().contains(&pointer.x)));
+
+        if let Some(to) = target.filter(|to| *to != from) {
+            ui.painter().rect_stroke(page_rects[to], 2.0, egui::Stroke::new(2.0, self.selected_color));
+        }
+
+        if !released {
+            return;
+        }
+        self.page_drag = None;
+
+        let to = match target.filter(|to| *to != from) {
+            Some(to) => to,
+            None => return,
+        };
+
+        let track = self.synth.track_mut();
+        if swap {
+            track.swap_pages(from, to);
+            self.follow_pages(|page| if page == from {to} else if page == to {from} else {page});
+        }
+        else {
+            track.move_page(from, to);
+            self.follow_pages(|page| moved_page(page, from, to));
+        }
+    }
+
+    /// Keeps the selected page and the scrubber on the same notes after pages are
+    /// moved around. new_index gives the index each page ended up at
+    fn follow_pages(&mut self, new_index: impl Fn(usize) -> usize){
+        let rows_per_page = self.synth.get_notes_per_page() as usize;
+        let (page, row) = (self.scrubber_start / rows_per_page, self.scrubber_start % rows_per_page);
+
+        self.selected_page = new_index(self.selected_page);
+        self.scrubber_start = new_index(page) * rows_per_page + row;
+    }
+
+    /// The patterns of one page, and a button that removes it along with every page after it.
+    /// The page number can be dragged to move the page, or right clicked for the page menu.
+    /// Right clicking a pattern changes which pattern the page plays
+    fn order_column(&mut self, ui: &mut egui::Ui, page_index: usize){
+        let header = ui.add(egui::Label::new(format!("{:02X}", page_index)).sense(Sense::click_and_drag()))
+            .on_hover_text("Drag to move this page, or hold shift to swap it with another");
+
+        if header.drag_started() {
+            self.page_drag = Some(page_index);
+        }
+        header.context_menu(|ui| self.page_menu(ui, page_index));
+
+        if ui.button("–").clicked() {
+            self.page_edit = Some(PageEdit::RemoveFrom(page_index));
+        }
+
+        for j in 0 .. self.synth.track().get_channel_count(){
+            let pattern = self.synth.track().orders[page_index][j];
+            let uses = self.synth.track().get_pattern_uses(j, pattern);
+
+            let response = ui.add(
+            egui::Button::new(format!("{} {:02X}", self.channel_symbol[j], pattern))
+            .fill(
+                if page_index == self.selected_page && j == self.selected_channel {self.selected_color} 
+                else { self.unselected_color}
+            ))
+            .on_hover_text(format!("Pattern {:02X}, played on {} page(s)", pattern, uses));
+
+            if response.clicked(){
+                self.selected_channel = j;
+                self.selected_page = page_index;
             }
-            
-            for j in 0 .. self.synth.track.get_channel_count(){
-                // TODO turn this into a function
-                if ui.add(
-                egui::Button::new(format!("{}",self.channel_symbol[j]))
-                .fill(
-                    if measure_index == self.selected_page && j == self.selected_channel {self.selected_color} 
-                    else { self.unselected_color}
-                ))
-                .clicked(){
-                    self.selected_channel = j;
-                    self.selected_page = measure_index;
-                    println!("{},{}", self.selected_page, self.selected_channel);
-                };
+
+            response.context_menu(|ui| self.pattern_menu(ui, page_index, j));
+        }
+    }
+
+    /// The right click menu of a page number in the order editor. Every channel of the page moves together
+    fn page_menu(&mut self, ui: &mut egui::Ui, page_index: usize){
+        let track = self.synth.track_mut();
+        let last_page = track.get_page_count() - 1;
+
+        let mut duplicated = false;
+        let mut moved_to = None;
+
+        if ui.button("Duplicate Page").on_hover_text("The copy plays the same patterns").clicked() {
+            track.duplicate_page(page_index, false);
+            duplicated = true;
+        }
+        if ui.button("Duplicate with New Patterns").on_hover_text("The copy plays copies of the patterns").clicked() {
+            track.duplicate_page(page_index, true);
+            duplicated = true;
+        }
+
+        ui.separator();
+
+        if ui.add_enabled(page_index > 0, egui::Button::new("Move Left")).clicked() {
+            track.move_page(page_index, page_index - 1);
+            moved_to = Some(page_index - 1);
+        }
+        if ui.add_enabled(page_index < last_page, egui::Button::new("Move Right")).clicked() {
+            track.move_page(page_index, page_index + 1);
+            moved_to = Some(page_index + 1);
+        }
+        if ui.add_enabled(page_index > 0, egui::Button::new("Move to Start")).clicked() {
+            track.move_page(page_index, 0);
+            moved_to = Some(0);
+        }
+        if ui.add_enabled(page_index < last_page, egui::Button::new("Move to End")).clicked() {
+            track.move_page(page_index, last_page);
+            moved_to = Some(last_page);
+        }
+
+        // The copy is inserted after the page, pushing the pages after it along
+        if duplicated {
+            self.follow_pages(|page| if page > page_index {page + 1} else {page});
+            ui.close_menu();
+        }
+        if let Some(to) = moved_to {
+            self.follow_pages(|page| moved_page(page, page_index, to));
+            ui.close_menu();
+        }
+    }
+
+    /// The right click menu of a pattern in the order editor. Pages playing the same
+    /// pattern share their notes, until one of them is made unique
+    fn pattern_menu(&mut self, ui: &mut egui::Ui, page_index: usize, channel: usize){
+        let track = self.synth.track_mut();
+        let mut pattern = track.orders[page_index][channel];
+        let uses = track.get_pattern_uses(channel, pattern);
+
+        ui.horizontal(|ui| {
+            ui.label("Pattern");
+
+            // One past the last pattern creates a new one
+            let last_pattern = track.patterns[channel].len();
+            if ui.add(egui::DragValue::new(&mut pattern).clamp_range(0..=last_pattern).hexadecimal(2, false, true)).changed() {
+                track.set_order(page_index, channel, pattern);
             }
+        });
+
+        if ui.button("New Pattern").clicked() {
+            let new_pattern = track.new_pattern(channel, None);
+            track.set_order(page_index, channel, new_pattern);
+            ui.close_menu();
+        }
+
+        if ui.add_enabled(uses > 1, egui::Button::new("Make Unique")).clicked() {
+            let new_pattern = track.new_pattern(channel, Some(pattern));
+            track.set_order(page_index, channel, new_pattern);
+            ui.close_menu();
+        }
+
+        ui.separator();
+
+        let rows_per_page = track.get_rows_per_page() as usize;
+        let page_start = page_index * rows_per_page;
+
+        if ui.button("Insert Page Before").clicked() {
+            self.page_edit = Some(PageEdit::InsertBefore(page_index));
+            ui.close_menu();
+        }
+        if ui.add_enabled(track.get_page_count() > 1, egui::Button::new("Delete Page")).clicked() {
+            self.page_edit = Some(PageEdit::Delete(page_index));
+            ui.close_menu();
+        }
+        if ui.button("Insert Page in Channel").clicked() {
+            track.insert_columns(page_start, rows_per_page, Some(channel));
+            ui.close_menu();
+        }
+        if ui.button("Delete Page from Channel").clicked() {
+            track.delete_columns(page_start, rows_per_page, Some(channel));
+            ui.close_menu();
+        }
     }
 
     fn add_channel_columns(&mut self, ui: &mut egui::Ui,){
@@ -326,4 +1692,51 @@ impl RustNES{
             }
         });
     }
-}
\ No newline at end of file
+}
+
+/// Blends two colours, by amount from 0 for all of the first to 1 for all of the second
+fn mix_colors(first: Color32, second: Color32, amount: f32) -> Color32{
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount) as u8;
+    Color32::from_rgb(mix(first.r(), second.r()), mix(first.g(), second.g()), mix(first.b(), second.b()))
+}
+
+/// The index a page ends up at after the page at from is moved to to,
+/// as the pages between them shift over by one
+fn moved_page(page: usize, from: usize, to: usize) -> usize{
+    if page == from {
+        to
+    }
+    else if from < to && (from + 1 ..= to).contains(&page) {
+        page - 1
+    }
+    else if to < from && (to .. from).contains(&page) {
+        page + 1
+    }
+    else {
+        page
+    }
+}
+
+/// The name of a groove as shown in the menus, with its index in front
+fn groove_name(grooves: &[synth::Groove], groove_index: usize) -> String{
+    match grooves.get(groove_index) {
+        Some(groove) => format!("{:02} {}", groove_index, groove.name),
+        None => format!("{:02} (missing)", groove_index),
+    }
+}
+
+/// The name of a song as shown in the song selector, with its index in front
+fn song_name(songs: &[synth::Track], song_index: usize) -> String{
+    match songs.get(song_index) {
+        Some(track) => format!("{:02} {}", song_index, track.title),
+        None => format!("{:02} (missing)", song_index),
+    }
+}
+
+/// The name of an instrument as shown in the menus, with its index in front
+fn instrument_name(instruments: &[instrument::Instrument], instrument_index: usize) -> String{
+    match instruments.get(instrument_index) {
+        Some(instrument) => format!("{:02X} {}", instrument_index, instrument.name),
+        None => format!("{:02X} (missing)", instrument_index),
+    }
+}
@@ -0,0 +1,499 @@
+use super::synth::{Track, WaveColumn, NoteEvent};
+use super::effect::{Effect, EffectCommand};
+use super::waves::NOTE_COUNT;
+
+// The first line of a clip copied as text
+const CLIP_HEADER: &str = "RustNES clip";
+
+///
+/// A rectangle of the note stepper, from one column to another and one row to another.
+/// Columns are counted from the start of the song, so a selection can cross pages
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Selection{
+    pub(crate) first_column: usize,
+    pub(crate) last_column: usize,
+    pub(crate) low_row: u32,
+    pub(crate) high_row: u32,
+    /// The channel that is selected, or None for every channel
+    pub(crate) channel: Option<usize>,
+}
+
+impl Selection{
+    /// A selection between two corners, given as (column, row)
+    pub fn new(start: (usize, u32), end: (usize, u32), channel: Option<usize>) -> Self{
+        Self {
+            first_column: start.0.min(end.0),
+            last_column: start.0.max(end.0),
+            low_row: start.1.min(end.1),
+            high_row: start.1.max(end.1),
+            channel,
+        }
+    }
+
+    /// The channels inside the selection
+    pub fn channels(&self, channel_count: usize) -> Vec<usize>{
+        match self.channel {
+            Some(channel) => vec![channel],
+            None => (0 .. channel_count).collect(),
+        }
+    }
+
+    /// If the cell at column and row of a channel is inside the selection
+    pub fn contains(&self, column_index: usize, row_index: u32, channel: usize) -> bool{
+        (self.first_column ..= self.last_column).contains(&column_index)
+            && (self.low_row ..= self.high_row).contains(&row_index)
+            && (self.channel.is_none() || self.channel == Some(channel))
+    }
+
+    /// If a column's note is inside the rows of the selection.
+    /// Columns without a pitch, like releases, are always included
+    pub fn includes(&self, column: &WaveColumn) -> bool{
+        match column.get_row() {
+            Some(row) => (self.low_row ..= self.high_row).contains(&row),
+            None => true,
+        }
+    }
+
+    /// Moves every note in the selection up or down by semitones. Nothing is moved
+    /// if a note would go past the playable rows, and the selection moves with the notes,
+    /// stopping at the first and last rows. Returns false if the notes couldn't be moved
+    pub fn transpose(&mut self, track: &mut Track, semitones: i32, row_count: u32) -> bool{
+        let in_range = |row: u32| (0 .. row_count as i32).contains(&(row as i32 + semitones));
+
+        let fits = self.channels(track.get_channel_count()).into_iter()
+            .flat_map(|channel| (self.first_column ..= self.last_column).map(move |column_index| (column_index, channel)))
+            .filter_map(|(column_index, channel)| track.get_column(column_index, channel))
+            .filter(|column| self.includes(column))
+            .all(|column| column.get_row().is_none_or(in_range));
+
+        if !fits {
+            println!("Selection::transpose: Notes can't be moved past the playable rows");
+            return false;
+        }
+
+        for channel in self.channels(track.get_channel_count()) {
+            for column_index in self.first_column ..= self.last_column {
+                if let Some(column) = track.get_column_mut(column_index, channel) {
+                    if let (true, Some(row)) = (self.includes(column), column.get_row()) {
+                        column.set_row((row as i32 + semitones) as u32);
+                    }
+                }
+            }
+        }
+
+        let last_row = row_count.saturating_sub(1) as i32;
+        self.low_row = (self.low_row as i32 + semitones).clamp(0, last_row) as u32;
+        self.high_row = (self.high_row as i32 + semitones).clamp(0, last_row) as u32;
+        true
+    }
+
+    /// Flips the notes in the selection, so the last column plays first.
+    /// Each note still starts with a note on, so held notes stay tied
+    pub fn reverse(&mut self, track: &mut Track){
+        self.rearrange(track, 0, |columns| {
+            note_runs(columns).into_iter().rev().flat_map(reverse_run).collect()
+        });
+    }
+
+    /// Spreads the notes out to twice as many columns, doubling the length of each note.
+    /// Notes pushed past the end of the song are lost
+    pub fn double(&mut self, track: &mut Track){
+        self.rearrange(track, 0, |columns| {
+            columns.into_iter()
+                .flat_map(|column| {
+                    // The new column after each one holds its note, so the note lasts twice as long
+                    let hold = column.as_ref().filter(|column| plays_note(column)).map(held);
+                    [column, hold]
+                })
+                .collect()
+        });
+
+        let last_column = self.first_column + (self.last_column - self.first_column) * 2 + 1;
+        self.last_column = last_column.min(track.get_length() - 1);
+    }
+
+    /// Squeezes the notes into half as many columns, halving the length of each note.
+    /// Notes on every other column are dropped
+    pub fn halve(&mut self, track: &mut Track){
+        self.rearrange(track, 0, |columns| {
+            let mut halved = Vec::new();
+            for (offset, column) in columns.iter().enumerate().step_by(2) {
+                // A note that started on a dropped column starts on the column that holds it instead
+                let dropped = offset.checked_sub(1).and_then(|i| columns[i].as_ref());
+                halved.push(match (column, dropped) {
+                    (Some(column), Some(dropped)) if column.is_held() && dropped.get_event() == NoteEvent::On => Some(started(column, dropped.get_instrument())),
+                    _ => column.clone(),
+                });
+            }
+            halved
+        });
+        self.last_column = self.first_column + (self.last_column - self.first_column) / 2;
+    }
+
+    /// Moves the notes in the selection one column left or right, along with the selection.
+    /// Returns false if the selection is already at the start or end of the song
+    pub fn shift(&mut self, track: &mut Track, right: bool) -> bool{
+        if (!right && self.first_column == 0) || (right && self.last_column + 1 >= track.get_length()) {
+            println!("Selection::shift: The selection is already at the edge of the song");
+            return false;
+        }
+
+        let step: isize = if right {1} else {-1};
+        self.rearrange(track, step, |columns| columns);
+
+        self.first_column = (self.first_column as isize + step) as usize;
+        self.last_column = (self.last_column as isize + step) as usize;
+        true
+    }
+
+    /// Takes the notes out of the selection, and puts back the columns returned by arrange,
+    /// starting start columns from the first column. None is given for columns whose note is
+    /// outside the selection's rows, and None columns that are returned are left as they are.
+    /// Moved notes replace anything already in the column they land on
+    fn rearrange(&self, track: &mut Track, start: isize, arrange: impl Fn(Vec<Option<WaveColumn>>) -> Vec<Option<WaveColumn>>){
+        for channel in self.channels(track.get_channel_count()) {
+            let columns: Vec<Option<WaveColumn>> = (self.first_column ..= self.last_column)
+                .map(|column_index| track.get_column(column_index, channel).filter(|column| self.includes(column)).cloned())
+                .collect();
+
+            self.delete_channel(track, channel);
+
+            for (offset, column) in arrange(columns).into_iter().enumerate() {
+                let column = match column {
+                    Some(column) if column != WaveColumn::default() => column,
+                    _ => continue,
+                };
+
+                let target = match usize::try_from(self.first_column as isize + start + offset as isize) {
+                    Ok(target) => target,
+                    Err(_) => continue,
+                };
+
+                if let Some(target_column) = track.get_column_mut(target, channel) {
+                    *target_column = column;
+                }
+            }
+        }
+    }
+
+    /// Clears every note inside the selection on one channel
+    fn delete_channel(&self, track: &mut Track, channel: usize){
+        for column_index in self.first_column ..= self.last_column {
+            if let Some(column) = track.get_column_mut(column_index, channel) {
+                if self.includes(column) {
+                    *column = WaveColumn::default();
+                }
+            }
+        }
+    }
+
+    /// Clears every note inside the selection
+    pub fn delete(&self, track: &mut Track){
+        for channel in self.channels(track.get_channel_count()) {
+            self.delete_channel(track, channel);
+        }
+    }
+}
+
+///
+/// Columns copied from the note stepper. Notes outside the rows of the
+/// selection are copied as empty columns
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip{
+    /// The channel each run of columns was copied from
+    channels: Vec<(usize, Vec<WaveColumn>)>,
+}
+
+impl Clip{
+    pub fn copy(track: &Track, selection: &Selection) -> Self{
+        let channels = selection.channels(track.get_channel_count()).into_iter()
+            .map(|channel| {
+                let columns = (selection.first_column ..= selection.last_column)
+                    .map(|column_index| match track.get_column(column_index, channel) {
+                        Some(column) if selection.includes(column) => column.clone(),
+                        _ => WaveColumn::default(),
+                    })
+                    .collect();
+
+                (channel, columns)
+            })
+            .collect();
+
+        Self { channels }
+    }
+
+    /// Pastes the clip starting at column_index, replacing the columns under it.
+    /// A clip of one channel is pasted into the selected channel, otherwise
+    /// each channel is pasted back into the channel it was copied from
+    pub fn paste(&self, track: &mut Track, column_index: usize, selected_channel: usize){
+        let single_channel = self.channels.len() == 1;
+
+        for (channel, columns) in &self.channels {
+            let channel = if single_channel {selected_channel} else {*channel};
+
+            for (offset, clip_column) in columns.iter().enumerate() {
+                if let Some(column) = track.get_column_mut(column_index + offset, channel) {
+                    *column = clip_column.clone();
+                }
+            }
+        }
+    }
+
+    ///
+    /// The clip as text, so it can be shared outside of RustNES. After a header and the list
+    /// of channels, each line is one column, with a cell per channel split by "|".
+    /// A cell is the row, event, instrument, volume, and effect, with dots for anything missing:
+    /// `12 N 00 F 4A3 | .. . .. . ...`
+    ///
+    pub fn to_text(&self) -> String{
+        let channels: Vec<String> = self.channels.iter().map(|(channel, _)| channel.to_string()).collect();
+        let mut text = format!("{}\nchannels {}\n", CLIP_HEADER, channels.join(" "));
+
+        let length = self.channels.first().map_or(0, |(_, columns)| columns.len());
+        for i in 0 .. length {
+            let cells: Vec<String> = self.channels.iter().map(|(_, columns)| cell_text(&columns[i])).collect();
+            text.push_str(&cells.join(" | "));
+            text.push('\n');
+        }
+
+        text
+    }
+
+    /// Reads a clip written by to_text. Returns None if the text isn't a clip
+    pub fn from_text(text: &str) -> Option<Self>{
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+
+        if lines.next()? != CLIP_HEADER {
+            return None;
+        }
+
+        let mut channels: Vec<(usize, Vec<WaveColumn>)> = lines.next()?
+            .strip_prefix("channels")?
+            .split_whitespace()
+            .map(|channel| channel.parse().ok().filter(|channel| *channel < 4).map(|channel| (channel, Vec::new())))
+            .collect::<Option<_>>()?;
+
+        if channels.is_empty() {
+            return None;
+        }
+
+        for line in lines {
+            let cells: Vec<&str> = line.split('|').collect();
+            if cells.len() != channels.len() {
+                return None;
+            }
+
+            for ((_, columns), cell) in channels.iter_mut().zip(cells) {
+                columns.push(parse_cell(cell)?);
+            }
+        }
+
+        Some(Self { channels })
+    }
+}
+
+/// If a column starts or holds a note
+fn plays_note(column: &WaveColumn) -> bool{
+    matches!(column.get_event(), NoteEvent::On | NoteEvent::Hold) && column.get_row().is_some()
+}
+
+/// A column that only holds the note of another column
+fn held(column: &WaveColumn) -> WaveColumn{
+    let mut held = WaveColumn::default();
+    if let Some(row) = column.get_row() {
+        held.hold(row);
+    }
+    held
+}
+
+/// A column that starts its note with an instrument, rather than holding it
+fn started(column: &WaveColumn, instrument: Option<u8>) -> WaveColumn{
+    let mut started = column.clone();
+    if let Some(row) = column.get_row() {
+        started.remove();
+        started.select(row);
+    }
+    started.set_instrument(instrument);
+    started
+}
+
+/// Splits columns into runs, where each note and the columns holding it are one run,
+/// and every other column is a run of its own
+fn note_runs(columns: Vec<Option<WaveColumn>>) -> Vec<Vec<Option<WaveColumn>>>{
+    let mut runs: Vec<Vec<Option<WaveColumn>>> = Vec::new();
+
+    for column in columns {
+        let holds = matches!(&column, Some(column) if column.is_held() && plays_note(column));
+        match runs.last_mut() {
+            Some(run) if holds && matches!(run.last(), Some(Some(last)) if plays_note(last)) => run.push(column),
+            _ => runs.push(vec![column]),
+        }
+    }
+
+    runs
+}
+
+/// Flips a run of a note, so it starts with a note on at the pitch it used to end
+/// on, with the instrument it used to start with, and holds the rest
+fn reverse_run(run: Vec<Option<WaveColumn>>) -> Vec<Option<WaveColumn>>{
+    if run.len() < 2 {
+        return run;
+    }
+
+    let instrument = run[0].as_ref().and_then(|column| column.get_instrument());
+
+    run.into_iter().rev().flatten().enumerate()
+        .map(|(i, column)| {
+            if i == 0 {
+                return Some(started(&column, instrument));
+            }
+
+            let mut held = column.clone();
+            if let Some(row) = column.get_row() {
+                held.hold(row);
+            }
+            held.set_instrument(None);
+            Some(held)
+        })
+        .collect()
+}
+
+fn cell_text(column: &WaveColumn) -> String{
+    let row = column.get_row().map_or("..".to_owned(), |row| format!("{:02}", row));
+    let event = match column.get_event() {
+        NoteEvent::On => 'N',
+        NoteEvent::Hold => 'H',
+        NoteEvent::Release => 'R',
+        NoteEvent::Empty => '.',
+    };
+    let instrument = column.get_instrument().map_or("..".to_owned(), |instrument| format!("{:02X}", instrument));
+    let volume = column.get_volume().map_or(".".to_owned(), |volume| format!("{:X}", volume));
+    let effect = column.get_effect().map_or("...".to_owned(), |effect| effect.to_string());
+
+    format!("{} {} {} {} {}", row, event, instrument, volume, effect)
+}
+
+fn parse_cell(cell: &str) -> Option<WaveColumn>{
+    let fields: Vec<&str> = cell.split_whitespace().collect();
+    if fields.len() != 5 {
+        return None;
+    }
+
+    let row = match fields[0] {
+        ".." => None,
+        row => Some(row.parse::<u32>().ok().filter(|row| *row < NOTE_COUNT as u32)?),
+    };
+
+    let mut column = WaveColumn::default();
+    match (fields[1], row) {
+        ("N", Some(row)) => column.select(row),
+        ("H", Some(row)) => column.hold(row),
+        ("R", None) => column.release(),
+        (".", None) => (),
+        _ => return None,
+    }
+
+    if fields[2] != ".." {
+        column.set_instrument(Some(u8::from_str_radix(fields[2], 16).ok()?));
+    }
+    if fields[3] != "." {
+        column.set_volume(Some(u8::from_str_radix(fields[3], 16).ok()?));
+    }
+    if fields[4] != "..." {
+        let mut chars = fields[4].chars();
+        let symbol = chars.next()?;
+        let command = EffectCommand::ALL.into_iter().find(|command| command.symbol() == symbol)?;
+        let param = u8::from_str_radix(chars.as_str(), 16).ok()?;
+
+        column.set_effect(Some(Effect { command, param }));
+    }
+
+    Some(column)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn note(row: u32, event: NoteEvent) -> WaveColumn{
+        let mut column = WaveColumn::default();
+        match event {
+            NoteEvent::On => column.select(row),
+            NoteEvent::Hold => column.hold(row),
+            NoteEvent::Release => column.release(),
+            NoteEvent::Empty => (),
+        }
+        column
+    }
+
+    fn test_clip() -> Clip{
+        let mut first = note(12, NoteEvent::On);
+        first.set_instrument(Some(0x1A));
+        first.set_volume(Some(15));
+        first.set_effect(Some(Effect { command: EffectCommand::Vibrato, param: 0xA3 }));
+
+        Clip {
+            channels: vec![
+                (0, vec![first, note(14, NoteEvent::Hold), note(0, NoteEvent::Release), WaveColumn::default()]),
+                (2, vec![WaveColumn::default(), note(NOTE_COUNT as u32 - 1, NoteEvent::On), note(3, NoteEvent::Hold), note(0, NoteEvent::Release)]),
+            ],
+        }
+    }
+
+    #[test]
+    fn text_round_trip(){
+        let clip = test_clip();
+        assert_eq!(Clip::from_text(&clip.to_text()), Some(clip));
+    }
+
+    #[test]
+    fn reads_the_readme_example(){
+        let clip = Clip::from_text("RustNES clip\nchannels 0\n12 N 00 F 4A3\n.. R .. . ...\n").unwrap();
+        let text = clip.to_text();
+
+        assert_eq!(text, "RustNES clip\nchannels 0\n12 N 00 F 4A3\n.. R .. . ...\n");
+    }
+
+    #[test]
+    fn other_text_is_not_a_clip(){
+        assert_eq!(Clip::from_text("hello"), None);
+        assert_eq!(Clip::from_text("RustNES clip\nchannels 7\n"), None);
+        assert_eq!(Clip::from_text("RustNES clip\nchannels 0 1\n12 N 00 F 4A3\n"), None);
+        assert_eq!(Clip::from_text(&format!("RustNES clip\nchannels 0\n{} N .. . ...\n", NOTE_COUNT)), None);
+    }
+
+    #[test]
+    fn copy_and_paste_round_trip(){
+        let mut track = Track::default();
+        let clip = test_clip();
+        clip.paste(&mut track, 3, 0);
+
+        let selection = Selection::new((3, 0), (6, NOTE_COUNT as u32 - 1), None);
+        let copied = Clip::copy(&track, &selection);
+
+        assert_eq!(copied.channels[0], clip.channels[0]);
+        assert_eq!(copied.channels[2], clip.channels[1]);
+    }
+
+    #[test]
+    fn transpose_only_checks_the_notes(){
+        let mut track = Track::default();
+        let last_row = NOTE_COUNT as u32 - 1;
+        *track.get_column_mut(0, 0).unwrap() = note(10, NoteEvent::On);
+
+        // The selection reaches the top row, but the only note has room to move
+        let mut selection = Selection::new((0, 5), (3, last_row), Some(0));
+        assert!(selection.transpose(&mut track, 12, NOTE_COUNT as u32));
+        assert_eq!(track.get_column(0, 0).unwrap().get_row(), Some(22));
+        assert_eq!((selection.low_row, selection.high_row), (17, last_row));
+
+        assert!(selection.transpose(&mut track, -20, NOTE_COUNT as u32));
+        assert_eq!(track.get_column(0, 0).unwrap().get_row(), Some(2));
+        assert_eq!((selection.low_row, selection.high_row), (0, last_row - 20));
+
+        assert!(!selection.transpose(&mut track, -3, NOTE_COUNT as u32));
+        assert_eq!(track.get_column(0, 0).unwrap().get_row(), Some(2));
+    }
+}
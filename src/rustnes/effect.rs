@@ -0,0 +1,344 @@
+use serde::{Serialize, Deserialize};
+
+/// The effect commands a column can have, named after their FamiTracker letters
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum EffectCommand{
+    /// 0xy, cycles between the note, x semitones up, and y semitones up every frame
+    Arpeggio,
+    /// 1xx, slides the pitch up by xx every frame
+    SlideUp,
+    /// 2xx, slides the pitch down by xx every frame
+    SlideDown,
+    /// 3xx, slides into new notes at xx every frame instead of restarting them
+    Portamento,
+    /// 4xy, wobbles the pitch at speed x with depth y
+    Vibrato,
+    /// Axy, raises the volume at speed x, or lowers it at speed y
+    VolumeSlide,
+    /// Gxx, waits xx frames before playing the column's note
+    NoteDelay,
+    /// Sxx, cuts the note after xx frames
+    NoteCut,
+}
+
+impl EffectCommand{
+    pub const ALL: [EffectCommand; 8] = [
+        EffectCommand::Arpeggio,
+        EffectCommand::SlideUp,
+        EffectCommand::SlideDown,
+        EffectCommand::Portamento,
+        EffectCommand::Vibrato,
+        EffectCommand::VolumeSlide,
+        EffectCommand::NoteDelay,
+        EffectCommand::NoteCut,
+    ];
+
+    /// The letter the command is written with in a tracker
+    pub fn symbol(&self) -> char{
+        match self {
+            EffectCommand::Arpeggio => '0',
+            EffectCommand::SlideUp => '1',
+            EffectCommand::SlideDown => '2',
+            EffectCommand::Portamento => '3',
+            EffectCommand::Vibrato => '4',
+            EffectCommand::VolumeSlide => 'A',
+            EffectCommand::NoteDelay => 'G',
+            EffectCommand::NoteCut => 'S',
+        }
+    }
+
+    pub fn name(&self) -> &'static str{
+        match self {
+            EffectCommand::Arpeggio => "Arpeggio",
+            EffectCommand::SlideUp => "Slide Up",
+            EffectCommand::SlideDown => "Slide Down",
+            EffectCommand::Portamento => "Portamento",
+            EffectCommand::Vibrato => "Vibrato",
+            EffectCommand::VolumeSlide => "Volume Slide",
+            EffectCommand::NoteDelay => "Note Delay",
+            EffectCommand::NoteCut => "Note Cut",
+        }
+    }
+}
+
+/// An effect command and its parameter, like "4A3"
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Effect{
+    pub(crate) command: EffectCommand,
+    pub(crate) param: u8,
+}
+
+impl std::fmt::Display for Effect{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result{
+        write!(f, "{}{:02X}", self.command.symbol(), self.param)
+    }
+}
+
+// Vibrato moves through a sine wave of this many steps
+const VIBRATO_STEPS: f32 = 64.0;
+
+///
+/// The effects that are running on a channel. Most effects keep going on
+/// later columns, until they are turned off with a parameter of 00
+///
+#[derive(Clone, Debug)]
+pub struct EffectState{
+    arpeggio: u8,
+    arpeggio_step: usize,
+
+    // How much the period changes every frame, positive is lower
+    slide: i32,
+    portamento_speed: i32,
+    period_offset: i32,
+
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_phase: u8,
+
+    // In 1/8ths of a volume step every frame
+    volume_slide: i32,
+    volume: f32,
+
+    cut_timer: Option<u8>,
+}
+
+impl Default for EffectState{
+    fn default() -> Self{
+        Self {
+            arpeggio: 0,
+            arpeggio_step: 0,
+            slide: 0,
+            portamento_speed: 0,
+            period_offset: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_phase: 0,
+            volume_slide: 0,
+            volume: 15.0,
+            cut_timer: None,
+        }
+    }
+}
+
+impl EffectState{
+    /// Starts an effect at the beginning of a column.
+    /// Note delays are handled by the voice, as they hold back the whole column
+    pub fn apply(&mut self, effect: Effect){
+        let x = effect.param >> 4;
+        let y = effect.param & 0x0F;
+
+        match effect.command {
+            EffectCommand::Arpeggio => {
+                self.arpeggio = effect.param;
+                self.arpeggio_step = 0;
+            },
+            EffectCommand::SlideUp => self.slide = -(effect.param as i32),
+            EffectCommand::SlideDown => self.slide = effect.param as i32,
+            EffectCommand::Portamento => self.portamento_speed = effect.param as i32,
+            EffectCommand::Vibrato => {
+                self.vibrato_speed = x;
+                self.vibrato_depth = y;
+            },
+            EffectCommand::VolumeSlide => self.volume_slide = x as i32 - y as i32,
+            EffectCommand::NoteCut => self.cut_timer = Some(effect.param),
+            EffectCommand::NoteDelay => (),
+        }
+    }
+
+    /// If new notes should slide from the current pitch instead of restarting
+    pub fn is_portamento(&self) -> bool{
+        self.portamento_speed > 0
+    }
+
+    /// Called when the note changes, with the periods of the old and new notes.
+    /// With portamento the pitch offset is moved so the current pitch is kept,
+    /// and then slides to the new note. Otherwise the slides start over
+    pub fn start_note(&mut self, old_period: Option<i32>, new_period: i32){
+        match old_period {
+            Some(old_period) if self.is_portamento() => self.period_offset += old_period - new_period,
+            _ => self.period_offset = 0,
+        }
+        self.arpeggio_step = 0;
+    }
+
+    /// Moves every effect on by one frame.
+    /// Returns true if the note should be cut
+    pub fn tick(&mut self) -> bool{
+        if self.arpeggio != 0 {
+            self.arpeggio_step = (self.arpeggio_step + 1) % 3;
+        }
+
+        self.period_offset += self.slide;
+
+        // Portamento slides the offset back to 0, where the new note is
+        if self.is_portamento() {
+            let distance = self.period_offset.abs().min(self.portamento_speed);
+            self.period_offset -= distance * self.period_offset.signum();
+        }
+
+        self.vibrato_phase = self.vibrato_phase.wrapping_add(self.vibrato_speed);
+        self.volume = (self.volume + self.volume_slide as f32 / 8.0).clamp(0.0, 15.0);
+
+        // Counted like a note delay, so Sxx lets the note play for xx frames
+        match self.cut_timer {
+            Some(timer) if timer <= 1 => {
+                self.cut_timer = None;
+                true
+            },
+            Some(timer) => {
+                self.cut_timer = Some(timer - 1);
+                false
+            },
+            None => false,
+        }
+    }
+
+    /// How many semitones the arpeggio moves the note this frame
+    pub fn arpeggio_offset(&self) -> i32{
+        match self.arpeggio_step {
+            1 => (self.arpeggio >> 4) as i32,
+            2 => (self.arpeggio & 0x0F) as i32,
+            _ => 0,
+        }
+    }
+
+    /// How far the slides, portamento, and vibrato have moved the period
+    pub fn period_offset(&self) -> i32{
+        let phase = (self.vibrato_phase as f32 % VIBRATO_STEPS) / VIBRATO_STEPS;
+        let vibrato = (phase * std::f32::consts::TAU).sin() * self.vibrato_depth as f32;
+
+        self.period_offset + vibrato.round() as i32
+    }
+
+    /// Sets the channel volume from 0 to 15, from a column's volume
+    pub fn set_volume(&mut self, volume: u8){
+        self.volume = volume as f32;
+    }
+
+    /// The channel volume from 0.0 to 1.0
+    pub fn volume(&self) -> f32{
+        self.volume / 15.0
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn effect(command: EffectCommand, param: u8) -> Effect{
+        Effect { command, param }
+    }
+
+    /// The value of something after each of a number of frames
+    fn frames<T>(effects: &mut EffectState, count: usize, value: impl Fn(&EffectState) -> T) -> Vec<T>{
+        (0 .. count).map(|_| {
+            effects.tick();
+            value(effects)
+        }).collect()
+    }
+
+    #[test]
+    fn arpeggio_cycles_through_three_notes(){
+        let mut effects = EffectState::default();
+        effects.apply(effect(EffectCommand::Arpeggio, 0x37));
+
+        assert_eq!(effects.arpeggio_offset(), 0);
+        assert_eq!(frames(&mut effects, 6, EffectState::arpeggio_offset), vec![3, 7, 0, 3, 7, 0]);
+
+        // A new note starts the arpeggio over, and 000 turns it off
+        effects.start_note(None, 100);
+        assert_eq!(effects.arpeggio_offset(), 0);
+        effects.apply(effect(EffectCommand::Arpeggio, 0));
+        assert_eq!(frames(&mut effects, 3, EffectState::arpeggio_offset), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn slides_move_the_period_every_frame(){
+        let mut effects = EffectState::default();
+        effects.apply(effect(EffectCommand::SlideUp, 2));
+        assert_eq!(frames(&mut effects, 3, EffectState::period_offset), vec![-2, -4, -6]);
+
+        effects.apply(effect(EffectCommand::SlideDown, 5));
+        assert_eq!(frames(&mut effects, 2, EffectState::period_offset), vec![-1, 4]);
+
+        // The slide keeps its place until turned off, and a new note starts from 0
+        effects.apply(effect(EffectCommand::SlideDown, 0));
+        assert_eq!(frames(&mut effects, 2, EffectState::period_offset), vec![4, 4]);
+        effects.start_note(Some(200), 100);
+        assert_eq!(effects.period_offset(), 0);
+    }
+
+    #[test]
+    fn portamento_slides_to_the_new_note(){
+        let mut effects = EffectState::default();
+        effects.apply(effect(EffectCommand::Portamento, 4));
+        assert!(effects.is_portamento());
+
+        // Moving from period 110 to 100 starts at the old pitch
+        effects.start_note(Some(110), 100);
+        assert_eq!(effects.period_offset(), 10);
+        assert_eq!(frames(&mut effects, 4, EffectState::period_offset), vec![6, 2, 0, 0]);
+
+        // Upwards as well as downwards
+        effects.start_note(Some(100), 109);
+        assert_eq!(frames(&mut effects, 3, EffectState::period_offset), vec![-5, -1, 0]);
+
+        effects.apply(effect(EffectCommand::Portamento, 0));
+        assert!(!effects.is_portamento());
+        effects.start_note(Some(100), 200);
+        assert_eq!(effects.period_offset(), 0);
+    }
+
+    #[test]
+    fn vibrato_follows_a_sine_wave(){
+        let mut effects = EffectState::default();
+        effects.apply(effect(EffectCommand::Vibrato, 0x44));
+
+        // 16 frames at speed 4 is a quarter of the wave, at its highest
+        let offsets = frames(&mut effects, 64, EffectState::period_offset);
+        assert_eq!(offsets[3], 4);
+        assert_eq!(offsets[7], 0);
+        assert_eq!(offsets[11], -4);
+        assert_eq!(offsets[15], 0);
+        assert!(offsets.iter().all(|offset| offset.abs() <= 4));
+
+        effects.apply(effect(EffectCommand::Vibrato, 0));
+        assert_eq!(effects.period_offset(), 0);
+    }
+
+    #[test]
+    fn volume_slides_in_eighths(){
+        let mut effects = EffectState::default();
+        effects.set_volume(8);
+        effects.apply(effect(EffectCommand::VolumeSlide, 0x04));
+
+        let volumes = frames(&mut effects, 4, |effects| effects.volume() * 15.0);
+        assert_eq!(volumes, vec![7.5, 7.0, 6.5, 6.0]);
+
+        // It stops at silence, and at full volume
+        let volumes = frames(&mut effects, 20, |effects| effects.volume());
+        assert_eq!(volumes.last(), Some(&0.0));
+
+        effects.apply(effect(EffectCommand::VolumeSlide, 0xF0));
+        let volumes = frames(&mut effects, 10, |effects| effects.volume());
+        assert_eq!(volumes.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn note_cut_after_its_frames(){
+        let mut effects = EffectState::default();
+        effects.apply(effect(EffectCommand::NoteCut, 3));
+        let cuts: Vec<bool> = (0 .. 4).map(|_| effects.tick()).collect();
+        assert_eq!(cuts, vec![false, false, true, false]);
+
+        let mut effects = EffectState::default();
+        effects.apply(effect(EffectCommand::NoteCut, 1));
+        assert!(effects.tick());
+    }
+
+    #[test]
+    fn effects_are_written_like_a_tracker(){
+        assert_eq!(effect(EffectCommand::Vibrato, 0xA3).to_string(), "4A3");
+        assert_eq!(effect(EffectCommand::NoteCut, 0x02).to_string(), "S02");
+    }
+}
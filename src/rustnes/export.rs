@@ -0,0 +1,630 @@
+use std::path::PathBuf;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::sync::Arc;
+
+use super::synth::Track;
+use super::instrument::Instrument;
+use super::waves::{Oscillators, ChannelMix, ChannelFrame};
+use super::timing::{SAMPLE_RATE, Region};
+
+// WAV files are written as 16 bit mono PCM
+const BITS_PER_SAMPLE: u16 = 16;
+const WAV_FORMAT_PCM: u16 = 1;
+
+// The APU registers written by exported songs, as offsets from $4000
+const APU_REGISTER_COUNT: usize = 0x18;
+const APU_STATUS: u8 = 0x15;
+const APU_PULSE_SWEEPS: [u8; 2] = [0x01, 0x05];
+// Turns on the pulse, triangle, and noise channels
+const APU_CHANNELS_ON: u8 = 0x0F;
+// Turns a pulse's sweep unit off without muting its low notes
+const SWEEP_OFF: u8 = 0x08;
+// Loads the length counter with a long length when a channel's high register is written.
+// Length counters are halted anyway, so notes last until they are turned off
+const LENGTH_LOAD: u8 = 0x08;
+
+// The NSF is loaded at $8000 and split into 4KB banks. The first bank holds the driver and the
+// tables of where each song starts, and the songs are read through the bank at $9000
+const NSF_LOAD_ADDRESS: u16 = 0x8000;
+const NSF_INIT_ADDRESS: u16 = 0x8000;
+const NSF_PLAY_ADDRESS: u16 = 0x8027;
+const NSF_BANK_SIZE: usize = 0x1000;
+const NSF_SONG_BANKS: usize = 0x100;
+const NSF_SONG_LOW_BYTES: usize = 0x200;
+const NSF_SONG_HIGH_BYTES: usize = 0x300;
+const NSF_STREAM_WINDOW: usize = 0x9000;
+// How long a frame lasts in microseconds, as written in the header
+const NSF_NTSC_SPEED: u16 = 16639;
+const NSF_PAL_SPEED: u16 = 19997;
+// An NSF can hold at most this many songs
+const NSF_MAX_SONGS: usize = 255;
+
+// The header's name, artist, and copyright are 31 ASCII characters and a null,
+// with unknown fields written as "<?>"
+const NSF_TEXT_SIZE: usize = 32;
+const NSF_UNKNOWN_TEXT: &str = "<?>";
+
+// In a song's stream, a byte below NSF_FRAME_END is a register followed by its value.
+// NSF_FRAME_END + n ends the frame and waits n more frames, and NSF_SONG_END stops the song
+const NSF_FRAME_END: u8 = 0x80;
+const NSF_MAX_WAIT: u8 = 0x7E;
+const NSF_SONG_END: u8 = 0xFF;
+
+// VGM files count time in samples at 44.1kHz, and hold the NES APU from version 1.61
+const VGM_VERSION: u32 = 0x161;
+const VGM_SAMPLE_RATE: u32 = 44100;
+const VGM_HEADER_SIZE: usize = 0x100;
+const VGM_NTSC_APU_CLOCK: u32 = 1789772;
+const VGM_PAL_APU_CLOCK: u32 = 1662607;
+// The commands of a VGM file
+const VGM_APU_WRITE: u8 = 0xB4;
+const VGM_WAIT: u8 = 0x61;
+const VGM_WAIT_NTSC_FRAME: u8 = 0x62;
+const VGM_WAIT_PAL_FRAME: u8 = 0x63;
+const VGM_END: u8 = 0x66;
+const GD3_VERSION: u32 = 0x100;
+
+///
+/// The 6502 driver that plays the songs of an NSF. Zero page $00-$01 points at the next byte
+/// of the song, $02 is the bank at $9000, $03 counts the frames left to wait, and $04 is set
+/// once the song has ended.
+///
+///     init:      TAY              ; A is the song to play
+///                LDA $8100,Y      ; the bank, low byte, and high byte of where the song starts
+///                STA $02
+///                STA $5FF9
+///                LDA $8200,Y
+///                STA $00
+///                LDA $8300,Y
+///                STA $01
+///                LDA #$00
+///                STA $03
+///                STA $04
+///                LDA #$0F         ; turn on every channel, and turn off the sweep units
+///                STA $4015
+///                LDA #$08
+///                STA $4001
+///                STA $4005
+///                RTS
+///     play:      LDA $04
+///                BNE done
+///                LDA $03
+///                BEQ read
+///                DEC $03
+///                RTS
+///     read:      JSR next_byte
+///                CMP #$80
+///                BCS frame_end
+///                TAX              ; a register, then its value
+///                JSR next_byte
+///                STA $4000,X
+///                JMP read
+///     frame_end: CMP #$FF
+///                BEQ stop
+///                AND #$7F
+///                STA $03
+///     done:      RTS
+///     stop:      LDA #$00
+///                STA $4015
+///                LDA #$01
+///                STA $04
+///                RTS
+///     next_byte: LDY #$00
+///                LDA ($00),Y
+///                INC $00
+///                BNE nb_done
+///                INC $01
+///                PHA              ; past $9FFF the next bank is switched in at $9000
+///                LDA $01
+///                CMP #$A0
+///                BNE nb_pop
+///                LDA #$90
+///                STA $01
+///                INC $02
+///                LDA $02
+///                STA $5FF9
+///     nb_pop:    PLA
+///     nb_done:   RTS
+///
+const NSF_DRIVER: [u8; 0x74] = [
+    0xA8, 0xB9, 0x00, 0x81, 0x85, 0x02, 0x8D, 0xF9, 0x5F, 0xB9, 0x00, 0x82, 0x85, 0x00, 0xB9, 0x00,
+    0x83, 0x85, 0x01, 0xA9, 0x00, 0x85, 0x03, 0x85, 0x04, 0xA9, 0x0F, 0x8D, 0x15, 0x40, 0xA9, 0x08,
+    0x8D, 0x01, 0x40, 0x8D, 0x05, 0x40, 0x60, 0xA5, 0x04, 0xD0, 0x20, 0xA5, 0x03, 0xF0, 0x03, 0xC6,
+    0x03, 0x60, 0x20, 0x56, 0x80, 0xC9, 0x80, 0xB0, 0x0A, 0xAA, 0x20, 0x56, 0x80, 0x9D, 0x00, 0x40,
+    0x4C, 0x32, 0x80, 0xC9, 0xFF, 0xF0, 0x05, 0x29, 0x7F, 0x85, 0x03, 0x60, 0xA9, 0x00, 0x8D, 0x15,
+    0x40, 0xA9, 0x01, 0x85, 0x04, 0x60, 0xA0, 0x00, 0xB1, 0x00, 0xE6, 0x00, 0xD0, 0x15, 0xE6, 0x01,
+    0x48, 0xA5, 0x01, 0xC9, 0xA0, 0xD0, 0x0B, 0xA9, 0x90, 0x85, 0x01, 0xE6, 0x02, 0xA5, 0x02, 0x8D,
+    0xF9, 0x5F, 0x68, 0x60,
+];
+
+///
+/// Renders a song and saves it as a .wav file, leaving out any channels mix mutes.
+/// The song's title, author, copyright, and comments are written to the file's INFO chunk
+///
+pub fn export_wav(track: &Track, instruments: &[Instrument], volume: f32, mix: Arc<ChannelMix>, path: PathBuf) -> std::io::Result<()>{
+    let sliced_track = Track::slice_new(0, track);
+    let sample_count = (sliced_track.get_duration() * SAMPLE_RATE) as usize;
+
+    let samples: Vec<i16> = Oscillators::new(&sliced_track, instruments, mix)
+        .take(sample_count)
+        .map(|sample| ((sample * volume).clamp(-1.0, 1.0) * i16::MAX as f32) as i16)
+        .collect();
+
+    let info = info_chunk(track);
+    let data_size = (samples.len() * 2) as u32;
+    let block_align = BITS_PER_SAMPLE / 8;
+
+    let mut file = BufWriter::new(File::create(path)?);
+
+    file.write_all(b"RIFF")?;
+    // The size of everything after this field: "WAVE", the fmt chunk, the INFO chunk, and the data chunk
+    file.write_all(&(4 + 24 + info.len() as u32 + 8 + data_size).to_le_bytes())?;
+    file.write_all(b"WAVE")?;
+
+    file.write_all(b"fmt ")?;
+    file.write_all(&16u32.to_le_bytes())?;
+    file.write_all(&WAV_FORMAT_PCM.to_le_bytes())?;
+    file.write_all(&1u16.to_le_bytes())?;
+    file.write_all(&(SAMPLE_RATE as u32).to_le_bytes())?;
+    file.write_all(&(SAMPLE_RATE as u32 * block_align as u32).to_le_bytes())?;
+    file.write_all(&block_align.to_le_bytes())?;
+    file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+    file.write_all(&info)?;
+
+    file.write_all(b"data")?;
+    file.write_all(&data_size.to_le_bytes())?;
+    for sample in samples {
+        file.write_all(&sample.to_le_bytes())?;
+    }
+
+    file.flush()
+}
+
+/// The LIST INFO chunk of a WAV file, holding the song's metadata.
+/// Empty fields are left out, and an empty Vec is returned if every field is empty
+fn info_chunk(track: &Track) -> Vec<u8>{
+    let fields = [
+        (b"INAM", &track.title),
+        (b"IART", &track.author),
+        (b"ICOP", &track.copyright),
+        (b"ICMT", &track.comments),
+    ];
+
+    let mut info = Vec::new();
+    for (id, text) in fields {
+        if text.is_empty() {
+            continue;
+        }
+
+        // Every string ends with a null, and chunks are padded to an even size
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.push(0);
+
+        info.extend_from_slice(id);
+        info.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        info.extend_from_slice(&bytes);
+    }
+
+    if info.is_empty() {
+        return info;
+    }
+
+    let mut chunk = b"LIST".to_vec();
+    chunk.extend_from_slice(&(4 + info.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(b"INFO");
+    chunk.extend_from_slice(&info);
+    chunk
+}
+
+///
+/// Saves every song of a project into one .nsf file, each as its own subtune. The songs are
+/// stored as the APU register writes of each frame, played back by a small driver.
+/// The whole file plays at the first song's region, leaving out any channels mix mutes.
+/// The first song's title, author, and copyright fill the header's name, artist, and copyright
+///
+pub fn export_nsf(songs: &[Track], instruments: &[Instrument], mix: Arc<ChannelMix>, path: PathBuf) -> std::io::Result<()>{
+    std::fs::write(path, nsf_file(songs, instruments, mix))
+}
+
+/// The bytes of an .nsf file holding songs as subtunes
+fn nsf_file(songs: &[Track], instruments: &[Instrument], mix: Arc<ChannelMix>) -> Vec<u8>{
+    if songs.len() > NSF_MAX_SONGS {
+        println!("export::nsf_file: Only the first {} songs fit in an NSF", NSF_MAX_SONGS);
+    }
+    let songs = &songs[.. songs.len().min(NSF_MAX_SONGS)];
+    let region = songs.first().map_or(Region::Ntsc, |track| track.region);
+
+    let mut driver = NSF_DRIVER.to_vec();
+    driver.resize(NSF_BANK_SIZE, 0);
+
+    let mut streams = Vec::new();
+    for (song, track) in songs.iter().enumerate() {
+        // Where the song starts, as the bank and address the driver reads it from
+        let bank = 1 + streams.len() / NSF_BANK_SIZE;
+        let address = NSF_STREAM_WINDOW + streams.len() % NSF_BANK_SIZE;
+        driver[NSF_SONG_BANKS + song] = bank as u8;
+        driver[NSF_SONG_LOW_BYTES + song] = address as u8;
+        driver[NSF_SONG_HIGH_BYTES + song] = (address >> 8) as u8;
+
+        streams.extend(nsf_stream(&register_frames(track, instruments, mix.clone(), region)));
+    }
+
+    let mut file = nsf_header(songs, region).to_vec();
+    file.extend(driver);
+    file.extend(streams);
+    file
+}
+
+/// The 128 byte header of an .nsf file holding songs
+fn nsf_header(songs: &[Track], region: Region) -> [u8; 0x80]{
+    let mut header = [0; 0x80];
+
+    header[0x00 .. 0x05].copy_from_slice(b"NESM\x1A");
+    header[0x05] = 1;
+    header[0x06] = songs.len() as u8;
+    header[0x07] = 1;
+    header[0x08 .. 0x0A].copy_from_slice(&NSF_LOAD_ADDRESS.to_le_bytes());
+    header[0x0A .. 0x0C].copy_from_slice(&NSF_INIT_ADDRESS.to_le_bytes());
+    header[0x0C .. 0x0E].copy_from_slice(&NSF_PLAY_ADDRESS.to_le_bytes());
+
+    if let Some(track) = songs.first() {
+        for (field, text) in [(0x0E, &track.title), (0x2E, &track.author), (0x4E, &track.copyright)] {
+            header[field .. field + NSF_TEXT_SIZE].copy_from_slice(&nsf_text(text));
+        }
+    }
+
+    header[0x6E .. 0x70].copy_from_slice(&NSF_NTSC_SPEED.to_le_bytes());
+    // The driver's bank at $8000 and the first bank of songs at $9000.
+    // Setting any bank turns on bankswitching
+    header[0x71] = 1;
+    header[0x78 .. 0x7A].copy_from_slice(&NSF_PAL_SPEED.to_le_bytes());
+    header[0x7A] = match region {
+        Region::Ntsc => 0,
+        Region::Pal => 1,
+    };
+
+    header
+}
+
+/// A text field of an NSF header. Characters that aren't ASCII become '?', and text
+/// that doesn't fit is cut short
+fn nsf_text(text: &str) -> [u8; NSF_TEXT_SIZE]{
+    let text = if text.is_empty() {NSF_UNKNOWN_TEXT} else {text};
+
+    let mut field = [0; NSF_TEXT_SIZE];
+    for (byte, character) in field.iter_mut().zip(text.chars().take(NSF_TEXT_SIZE - 1)) {
+        *byte = if character.is_ascii() {character as u8} else {b'?'};
+    }
+    field
+}
+
+/// A song's register writes as the stream of bytes the NSF driver reads.
+/// Frames without any writes are joined onto the wait of the frame before them
+fn nsf_stream(frames: &[Vec<(u8, u8)>]) -> Vec<u8>{
+    let mut stream = Vec::new();
+    // The last byte, if it ends a frame that can still wait longer
+    let mut frame_end: Option<usize> = None;
+
+    for writes in frames {
+        if let (true, Some(end)) = (writes.is_empty(), frame_end) {
+            stream[end] += 1;
+            if stream[end] == NSF_FRAME_END + NSF_MAX_WAIT {
+                frame_end = None;
+            }
+            continue;
+        }
+
+        for (register, value) in writes {
+            stream.push(*register);
+            stream.push(*value);
+        }
+        stream.push(NSF_FRAME_END);
+        frame_end = Some(stream.len() - 1);
+    }
+
+    stream.push(NSF_SONG_END);
+    stream
+}
+
+///
+/// Saves a song as a .vgm file of APU register writes, played at the song's region.
+/// The song's title and author are written to its GD3 tags, with the copyright and
+/// comments in the notes. Channels mix mutes are left out
+///
+pub fn export_vgm(track: &Track, instruments: &[Instrument], mix: Arc<ChannelMix>, path: PathBuf) -> std::io::Result<()>{
+    std::fs::write(path, vgm_file(track, instruments, mix))
+}
+
+/// The bytes of a .vgm file holding a song
+fn vgm_file(track: &Track, instruments: &[Instrument], mix: Arc<ChannelMix>) -> Vec<u8>{
+    let frames = register_frames(track, instruments, mix, track.region);
+    let (frame_samples, wait_frame, apu_clock) = match track.region {
+        Region::Ntsc => (VGM_SAMPLE_RATE / 60, VGM_WAIT_NTSC_FRAME, VGM_NTSC_APU_CLOCK),
+        Region::Pal => (VGM_SAMPLE_RATE / 50, VGM_WAIT_PAL_FRAME, VGM_PAL_APU_CLOCK),
+    };
+
+    let mut data = Vec::new();
+    let write = |data: &mut Vec<u8>, (register, value): (u8, u8)| data.extend([VGM_APU_WRITE, register, value]);
+
+    // Turn on every channel, and turn off the sweep units, like the NSF driver
+    write(&mut data, (APU_STATUS, APU_CHANNELS_ON));
+    for sweep in APU_PULSE_SWEEPS {
+        write(&mut data, (sweep, SWEEP_OFF));
+    }
+
+    // The song ends by turning every channel off. Waits are joined until the next frame with writes
+    let silence = vec![(APU_STATUS, 0x00)];
+    let mut wait = 0;
+    for writes in frames.iter().chain([&silence]) {
+        if !writes.is_empty() {
+            vgm_wait(&mut data, wait, frame_samples, wait_frame);
+            wait = 0;
+        }
+        for register in writes {
+            write(&mut data, *register);
+        }
+        wait += frame_samples;
+    }
+    data.push(VGM_END);
+
+    let gd3 = gd3_tags(track);
+    let total_samples = frames.len() as u32 * frame_samples;
+
+    let mut header = vec![0; VGM_HEADER_SIZE];
+    let mut set = |offset: usize, value: u32| header[offset .. offset + 4].copy_from_slice(&value.to_le_bytes());
+    // Offsets are counted from the field they are written in
+    set(0x00, u32::from_le_bytes(*b"Vgm "));
+    set(0x04, (VGM_HEADER_SIZE + data.len() + gd3.len() - 0x04) as u32);
+    set(0x08, VGM_VERSION);
+    set(0x14, (VGM_HEADER_SIZE + data.len() - 0x14) as u32);
+    set(0x18, total_samples);
+    set(0x24, track.region.frames_per_second());
+    set(0x34, (VGM_HEADER_SIZE - 0x34) as u32);
+    set(0x84, apu_clock);
+
+    let mut file = header;
+    file.extend(data);
+    file.extend(gd3);
+    file
+}
+
+/// Waits a number of samples, using the one byte waits for a single frame
+fn vgm_wait(data: &mut Vec<u8>, samples: u32, frame_samples: u32, wait_frame: u8){
+    if samples == frame_samples {
+        data.push(wait_frame);
+        return;
+    }
+
+    let mut samples = samples;
+    while samples > 0 {
+        let wait = samples.min(u16::MAX as u32);
+        data.push(VGM_WAIT);
+        data.extend((wait as u16).to_le_bytes());
+        samples -= wait;
+    }
+}
+
+/// The GD3 tags of a VGM file. Every field is a UTF-16 string ending with a null, and the
+/// Japanese names are left empty
+fn gd3_tags(track: &Track) -> Vec<u8>{
+    let notes = [&track.copyright, &track.comments].into_iter()
+        .filter(|text| !text.is_empty())
+        .map(|text| text.as_str())
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    // The track, game, system, and author names in English and Japanese,
+    // then the release date, the name of who made the file, and the notes
+    let fields = [&track.title, "", "", "", "Nintendo Entertainment System", "", &track.author, "", "", "", &notes];
+
+    let mut strings = Vec::new();
+    for field in fields {
+        for unit in field.encode_utf16().chain([0]) {
+            strings.extend(unit.to_le_bytes());
+        }
+    }
+
+    let mut tags = b"Gd3 ".to_vec();
+    tags.extend(GD3_VERSION.to_le_bytes());
+    tags.extend((strings.len() as u32).to_le_bytes());
+    tags.extend(strings);
+    tags
+}
+
+///
+/// Plays a song at the frame rate of a region, and returns the APU register writes of
+/// each frame. Registers are only written when they change, apart from the pulses' high
+/// period registers, which are written again on new notes to restart the pulse
+///
+fn register_frames(track: &Track, instruments: &[Instrument], mix: Arc<ChannelMix>, region: Region) -> Vec<Vec<(u8, u8)>>{
+    let mut track = Track::slice_new(0, track);
+    track.region = region;
+
+    let mut oscillators = Oscillators::new(&track, instruments, mix);
+    let mut registers = [None; APU_REGISTER_COUNT];
+    let mut frames = Vec::new();
+
+    while let Some(channels) = oscillators.next_frame() {
+        let mut writes = Vec::new();
+        for (channel, frame) in channels.iter().enumerate() {
+            for (register, value, restart) in channel_registers(channel, frame) {
+                if registers[register as usize] != Some(value) || restart {
+                    registers[register as usize] = Some(value);
+                    writes.push((register, value));
+                }
+            }
+        }
+        frames.push(writes);
+    }
+
+    frames
+}
+
+/// The registers of a channel for one frame, as (register, value, if it is written even when
+/// it hasn't changed). The period registers keep their last value while the channel is silent
+fn channel_registers(channel: usize, frame: &ChannelFrame) -> Vec<(u8, u8, bool)>{
+    let base = channel as u8 * 4;
+
+    // The length counters are halted and the volume is constant, so only the volume is set
+    let mut registers = match channel {
+        0 | 1 => vec![(base, (frame.duty << 6) | 0x30 | frame.volume, false)],
+        2 => vec![(base, if frame.period.is_some() {0xFF} else {0x80}, false)],
+        _ => vec![(base, 0x30 | frame.volume, false)],
+    };
+
+    if let Some(period) = frame.period {
+        // The noise period fits in the low register, and only the pulses restart on a new note
+        let high = if channel == 3 {LENGTH_LOAD} else {LENGTH_LOAD | (period >> 8) as u8};
+        registers.push((base + 2, period as u8, false));
+        registers.push((base + 3, high, frame.retrigger && channel < 2));
+    }
+
+    registers
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// A song with one note on the first column of the first pulse channel
+    fn one_note() -> Track{
+        let mut track = Track::default();
+        track.get_column_mut(0, 0).unwrap().select(20);
+        track
+    }
+
+    #[test]
+    fn registers_are_only_written_when_they_change(){
+        let frames = register_frames(&one_note(), &[], Arc::new(ChannelMix::default()), Region::Ntsc);
+
+        // Note 20 has a period of 640, played on a 50% duty at full volume
+        assert_eq!(frames[0], vec![(0x00, 0xBF), (0x02, 0x80), (0x03, 0x0A), (0x04, 0x30), (0x08, 0x80), (0x0C, 0x30)]);
+        assert_eq!(frames[1], vec![]);
+
+        // The note stops on the next column, which starts during the fourth frame at 16 columns a second
+        let silenced = frames.iter().position(|writes| !writes.is_empty() && writes != &frames[0]).unwrap();
+        assert_eq!(frames[silenced], vec![(0x00, 0x30)]);
+        assert_eq!(silenced, 3);
+
+        // Four pages of 32 columns at 16 columns a second
+        assert_eq!(frames.len(), 8 * 60);
+    }
+
+    #[test]
+    fn muted_channels_stay_silent(){
+        let mix = Arc::new(ChannelMix::default());
+        mix.set_muted(0, true);
+        let frames = register_frames(&one_note(), &[], mix, Region::Ntsc);
+
+        assert_eq!(frames[0], vec![(0x00, 0x30), (0x04, 0x30), (0x08, 0x80), (0x0C, 0x30)]);
+    }
+
+    #[test]
+    fn nsf_streams_join_empty_frames(){
+        let frames = vec![vec![(0x00, 0x3F)], vec![], vec![], vec![(0x02, 0x05)], vec![]];
+        assert_eq!(nsf_stream(&frames), vec![0x00, 0x3F, 0x82, 0x02, 0x05, 0x81, NSF_SONG_END]);
+
+        // A wait that runs out starts a new frame end: 1 + 126 frames, then 1 + 72 frames
+        let silence = vec![vec![]; 200];
+        assert_eq!(nsf_stream(&silence), vec![0xFE, 0xC8, NSF_SONG_END]);
+    }
+
+    #[test]
+    fn nsf_files_hold_every_song(){
+        let mut second = one_note();
+        second.region = Region::Pal;
+        let songs = [one_note(), second];
+        let file = nsf_file(&songs, &[], Arc::new(ChannelMix::default()));
+
+        assert_eq!(&file[0x00 .. 0x05], b"NESM\x1A");
+        assert_eq!(file[0x06], 2);
+        assert_eq!(&file[0x0C .. 0x0E], &[0x27, 0x80]);
+        assert_eq!(file[0x7A], 0);
+        assert_eq!(&file[0x80 .. 0x80 + NSF_DRIVER.len()], &NSF_DRIVER);
+
+        // The first song starts the first bank of songs, and the second follows straight after it
+        let first_length = nsf_stream(&register_frames(&songs[0], &[], Arc::new(ChannelMix::default()), Region::Ntsc)).len();
+        let table = |offset: usize, song: usize| file[0x80 + offset + song];
+        assert_eq!([table(NSF_SONG_BANKS, 0), table(NSF_SONG_LOW_BYTES, 0), table(NSF_SONG_HIGH_BYTES, 0)], [1, 0x00, 0x90]);
+        let address = 0x9000 + first_length;
+        assert_eq!([table(NSF_SONG_BANKS, 1), table(NSF_SONG_LOW_BYTES, 1), table(NSF_SONG_HIGH_BYTES, 1)], [1, address as u8, (address >> 8) as u8]);
+
+        assert_eq!(file.len(), 0x80 + NSF_BANK_SIZE + 2 * first_length);
+    }
+
+    #[test]
+    fn nsf_headers_hold_the_first_songs_details(){
+        let first = Track {
+            title: "Overworld".to_owned(),
+            author: "Zoë".to_owned(),
+            copyright: "x".repeat(40),
+            ..Track::default()
+        };
+        let header = nsf_header(&[first, Track::default()], Region::Ntsc);
+
+        assert_eq!(&header[0x0E .. 0x18], b"Overworld\0");
+        assert_eq!(&header[0x2E .. 0x32], b"Zo?\0");
+        assert_eq!(&header[0x4E .. 0x6D], "x".repeat(31).as_bytes());
+        assert_eq!(header[0x6D], 0);
+
+        assert_eq!(&nsf_text("")[.. 4], b"<?>\0");
+    }
+
+    #[test]
+    fn vgm_files_wait_between_writes(){
+        let file = vgm_file(&one_note(), &[], Arc::new(ChannelMix::default()));
+        let field = |offset: usize| u32::from_le_bytes(file[offset .. offset + 4].try_into().unwrap());
+
+        assert_eq!(&file[0x00 .. 0x04], b"Vgm ");
+        assert_eq!(field(0x04) as usize, file.len() - 0x04);
+        assert_eq!(field(0x08), 0x161);
+        assert_eq!(field(0x18), 8 * 44100);
+        assert_eq!(field(0x84), VGM_NTSC_APU_CLOCK);
+
+        // The setup, the first frame's writes, then a wait for the three frames until the note stops
+        let data = &file[0x34 + field(0x34) as usize ..];
+        assert_eq!(&data[.. 9], &[0xB4, 0x15, 0x0F, 0xB4, 0x01, 0x08, 0xB4, 0x05, 0x08]);
+        assert_eq!(&data[9 + 6 * 3 .. 9 + 6 * 3 + 6], &[VGM_WAIT, 0x9D, 0x08, 0xB4, 0x00, 0x30]);
+
+        // Then nothing for 477 frames until the song ends and every channel is turned off,
+        // waited out as five of the longest waits and one of 22920 samples
+        let end = &file[.. 0x14 + field(0x14) as usize];
+        assert_eq!(&end[end.len() - 22 ..], &[
+            VGM_WAIT, 0xFF, 0xFF, VGM_WAIT, 0xFF, 0xFF, VGM_WAIT, 0xFF, 0xFF, VGM_WAIT, 0xFF, 0xFF, VGM_WAIT, 0xFF, 0xFF,
+            VGM_WAIT, 0x88, 0x59, 0xB4, 0x15, 0x00, VGM_END,
+        ]);
+    }
+
+    #[test]
+    fn single_frame_waits_are_one_byte(){
+        let mut data = Vec::new();
+        vgm_wait(&mut data, 882, 882, VGM_WAIT_PAL_FRAME);
+        vgm_wait(&mut data, 70000, 882, VGM_WAIT_PAL_FRAME);
+        assert_eq!(data, vec![VGM_WAIT_PAL_FRAME, VGM_WAIT, 0xFF, 0xFF, VGM_WAIT, 0x71, 0x11]);
+    }
+
+    #[test]
+    fn gd3_tags_hold_the_songs_details(){
+        let track = Track {
+            title: "Title".to_owned(),
+            author: "Me".to_owned(),
+            copyright: "2024".to_owned(),
+            comments: "Hi".to_owned(),
+            ..Track::default()
+        };
+        let tags = gd3_tags(&track);
+
+        assert_eq!(&tags[.. 4], b"Gd3 ");
+        assert_eq!(u32::from_le_bytes(tags[8 .. 12].try_into().unwrap()) as usize, tags.len() - 12);
+
+        let units: Vec<u16> = tags[12 ..].chunks(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+        let fields: Vec<String> = String::from_utf16(&units).unwrap().split_terminator('\0').map(str::to_owned).collect();
+        assert_eq!(fields, ["Title", "", "", "", "Nintendo Entertainment System", "", "Me", "", "", "", "2024\nHi"]);
+    }
+}
@@ -0,0 +1,212 @@
+use super::synth::Project;
+
+// The most edits that can be undone, so the history doesn't grow forever
+const MAX_HISTORY: usize = 200;
+
+///
+/// The project as it was after one edit. Storing the project means every kind
+/// of edit can be undone the same way, including ones added later
+///
+#[derive(Clone, Debug)]
+struct State{
+    project: Project,
+    /// The song that was selected when the edit was made
+    song: usize,
+}
+
+///
+/// The undo and redo history, as a list of the project after each edit.
+/// Each state is both the result of one edit and the start of the next, so a
+/// project is only stored once per edit. Edits are recorded at checkpoints,
+/// so everything changed by one drag or one text field is undone together
+///
+#[derive(Debug)]
+pub struct History{
+    states: Vec<State>,
+    // The index of the state the project is in. States after it can be redone
+    position: usize,
+}
+
+impl History{
+    pub fn new(project: &Project) -> Self{
+        Self {
+            states: vec![State { project: project.clone(), song: 0 }],
+            position: 0,
+        }
+    }
+
+    /// Records any changes since the last checkpoint as one edit.
+    /// Making a new edit clears everything that could be redone
+    pub fn checkpoint(&mut self, project: &Project, song: usize){
+        if *project == self.states[self.position].project {
+            return;
+        }
+
+        self.states.truncate(self.position + 1);
+        self.states.push(State { project: project.clone(), song });
+        self.position += 1;
+
+        if self.states.len() > MAX_HISTORY + 1 {
+            self.states.remove(0);
+            self.position -= 1;
+        }
+    }
+
+    /// Forgets every edit, used after a project is created or opened
+    pub fn reset(&mut self, project: &Project){
+        *self = History::new(project);
+    }
+
+    pub fn can_undo(&self) -> bool{
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool{
+        self.position + 1 < self.states.len()
+    }
+
+    /// Puts the project back to how it was before the last edit.
+    /// Returns the song the edit was made in
+    pub fn undo(&mut self, project: &mut Project) -> Option<usize>{
+        if !self.can_undo() {
+            return None;
+        }
+
+        let song = self.states[self.position].song;
+        self.position -= 1;
+        *project = self.states[self.position].project.clone();
+        Some(song)
+    }
+
+    /// Makes the last undone edit again.
+    /// Returns the song the edit was made in
+    pub fn redo(&mut self, project: &mut Project) -> Option<usize>{
+        if !self.can_redo() {
+            return None;
+        }
+
+        self.position += 1;
+        let state = &self.states[self.position];
+        *project = state.project.clone();
+        Some(state.song)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// A project that can be told apart by its first song's title
+    fn titled(title: &str) -> Project{
+        let mut project = Project::default();
+        project.songs[0].title = title.to_owned();
+        project
+    }
+
+    fn title(project: &Project) -> &str{
+        &project.songs[0].title
+    }
+
+    #[test]
+    fn undo_and_redo(){
+        let mut project = titled("a");
+        let mut history = History::new(&project);
+        assert!(!history.can_undo());
+
+        for edit in ["b", "c"] {
+            project = titled(edit);
+            history.checkpoint(&project, 0);
+        }
+
+        assert_eq!(history.undo(&mut project), Some(0));
+        assert_eq!(title(&project), "b");
+        assert_eq!(history.undo(&mut project), Some(0));
+        assert_eq!(title(&project), "a");
+        assert_eq!(history.undo(&mut project), None);
+        assert_eq!(title(&project), "a");
+
+        assert_eq!(history.redo(&mut project), Some(0));
+        assert_eq!(title(&project), "b");
+        assert_eq!(history.redo(&mut project), Some(0));
+        assert_eq!(title(&project), "c");
+        assert_eq!(history.redo(&mut project), None);
+    }
+
+    #[test]
+    fn unchanged_projects_are_not_edits(){
+        let project = titled("a");
+        let mut history = History::new(&project);
+
+        history.checkpoint(&project, 0);
+        history.checkpoint(&project.clone(), 1);
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn undo_returns_the_song_of_the_edit(){
+        let mut project = titled("a");
+        let mut history = History::new(&project);
+
+        project = titled("b");
+        history.checkpoint(&project, 2);
+        project = titled("c");
+        history.checkpoint(&project, 1);
+
+        assert_eq!(history.undo(&mut project), Some(1));
+        assert_eq!(history.undo(&mut project), Some(2));
+        assert_eq!(history.redo(&mut project), Some(2));
+        assert_eq!(history.redo(&mut project), Some(1));
+    }
+
+    #[test]
+    fn new_edits_drop_the_redo_branch(){
+        let mut project = titled("a");
+        let mut history = History::new(&project);
+
+        project = titled("b");
+        history.checkpoint(&project, 0);
+        history.undo(&mut project);
+        assert!(history.can_redo());
+
+        project = titled("c");
+        history.checkpoint(&project, 0);
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(&mut project), None);
+
+        history.undo(&mut project);
+        assert_eq!(title(&project), "a");
+    }
+
+    #[test]
+    fn only_the_latest_edits_are_kept(){
+        let mut project = titled("start");
+        let mut history = History::new(&project);
+
+        for edit in 0 .. MAX_HISTORY + 10 {
+            project = titled(&edit.to_string());
+            history.checkpoint(&project, 0);
+        }
+
+        let mut undone = 0;
+        while history.undo(&mut project).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, MAX_HISTORY);
+        assert_eq!(title(&project), "9");
+    }
+
+    #[test]
+    fn reset_forgets_every_edit(){
+        let mut project = titled("a");
+        let mut history = History::new(&project);
+
+        project = titled("b");
+        history.checkpoint(&project, 0);
+        history.reset(&project);
+
+        assert!(!history.can_undo());
+        assert!(!history.can_redo());
+        assert_eq!(history.undo(&mut project), None);
+        assert_eq!(title(&project), "b");
+    }
+}
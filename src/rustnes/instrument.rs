@@ -0,0 +1,463 @@
+use std::path::PathBuf;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
+
+use serde::{Serialize, Deserialize};
+
+// Every .rni (RustNES Instrument) file starts with these bytes followed by the format version
+const RNI_MAGIC: &[u8; 4] = b"RNI\0";
+const RNI_VERSION: u32 = 1;
+
+// FamiTracker instrument files start with "FTI" and a version such as "2.4"
+const FTI_MAGIC: &[u8; 3] = b"FTI";
+// The .fti versions that can be read, as the version without its dot.
+// Release points were added after 2.0, and sequence settings in 2.2
+const FTI_VERSIONS: std::ops::RangeInclusive<u32> = 20 ..= 24;
+// The instrument type of a 2A03 instrument, the only type the NES has without expansion chips
+const FTI_2A03: u8 = 1;
+
+// FamiTracker's arpeggio settings. Absolute steps are added to the note, relative
+// steps add up over time, and fixed steps are notes of their own
+const FTI_ARPEGGIO_ABSOLUTE: u32 = 0;
+const FTI_ARPEGGIO_FIXED: u32 = 1;
+const FTI_ARPEGGIO_RELATIVE: u32 = 2;
+
+/// The sequences that make up an instrument, in the order they are stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SequenceKind{
+    Volume,
+    Arpeggio,
+    Pitch,
+    HiPitch,
+    Duty,
+}
+
+impl SequenceKind{
+    pub const ALL: [SequenceKind; 5] = [
+        SequenceKind::Volume,
+        SequenceKind::Arpeggio,
+        SequenceKind::Pitch,
+        SequenceKind::HiPitch,
+        SequenceKind::Duty,
+    ];
+
+    pub fn name(&self) -> &'static str{
+        match self {
+            SequenceKind::Volume => "Volume",
+            SequenceKind::Arpeggio => "Arpeggio",
+            SequenceKind::Pitch => "Pitch",
+            SequenceKind::HiPitch => "Hi-Pitch",
+            SequenceKind::Duty => "Duty",
+        }
+    }
+
+    /// The smallest and largest value a step of this sequence can have
+    pub fn range(&self) -> (i8, i8){
+        match self {
+            SequenceKind::Volume => (0, 15),
+            SequenceKind::Arpeggio => (-24, 24),
+            SequenceKind::Pitch | SequenceKind::HiPitch => (-64, 63),
+            SequenceKind::Duty => (0, 3),
+        }
+    }
+}
+
+///
+/// A list of values played one per engine frame, like a FamiTracker macro.
+/// An empty sequence is disabled, and leaves the channel at its default
+///
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Sequence{
+    pub(crate) values: Vec<i8>,
+    /// The step to jump back to after the last step
+    pub(crate) loop_point: Option<usize>,
+    /// The first step of the release part. Playback waits before this step
+    /// until the note is released
+    pub(crate) release_point: Option<usize>,
+}
+
+impl Sequence{
+    /// Changes the number of steps, filling new steps with the last value.
+    /// Loop and release points past the end are removed
+    pub fn resize(&mut self, length: usize){
+        let last = self.values.last().copied().unwrap_or(0);
+        self.values.resize(length, last);
+
+        self.loop_point = self.loop_point.filter(|point| *point < length);
+        self.release_point = self.release_point.filter(|point| *point < length);
+    }
+
+    /// The step after position, following the loop and release points.
+    /// Before the release only a loop in front of the release point is used,
+    /// and after it only a loop inside the release part
+    pub fn next_position(&self, position: usize, released: bool) -> usize{
+        let end = match self.release_point {
+            Some(release_point) if !released => release_point,
+            _ => self.values.len(),
+        };
+
+        if position + 1 < end {
+            return position + 1;
+        }
+
+        match self.loop_point {
+            Some(loop_point) if loop_point < end
+                && (!released || !matches!(self.release_point, Some(release_point) if loop_point < release_point)) => loop_point,
+            _ => position,
+        }
+    }
+}
+
+///
+/// A reusable sound, made of a volume, arpeggio, pitch, hi-pitch, and duty sequence
+///
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Instrument{
+    pub(crate) name: String,
+    pub(crate) sequences: [Sequence; 5],
+}
+
+impl Default for Instrument{
+    /// A short plucked sound, so a new instrument is audible straight away
+    fn default() -> Self {
+        let mut instrument = Self {
+            name: "New Instrument".to_owned(),
+            sequences: Default::default(),
+        };
+        instrument.sequences[SequenceKind::Volume as usize].values = vec![15, 13, 11, 10, 9, 8, 7, 6];
+        instrument
+    }
+}
+
+impl Instrument{
+    pub fn sequence(&self, kind: SequenceKind) -> &Sequence{
+        &self.sequences[kind as usize]
+    }
+
+    pub fn sequence_mut(&mut self, kind: SequenceKind) -> &mut Sequence{
+        &mut self.sequences[kind as usize]
+    }
+
+    /// Saves the instrument as an .rni file, so it can be used in other songs
+    pub fn save(&self, path: PathBuf) -> std::io::Result<()>{
+        let encoded_instrument: Vec<u8> = bincode::serialize(self)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut file = File::create(path)?;
+        file.write_all(RNI_MAGIC)?;
+        file.write_all(&RNI_VERSION.to_le_bytes())?;
+        file.write_all(&encoded_instrument)
+    }
+
+    /// Loads an instrument from either an .rni file, or a FamiTracker .fti file
+    pub fn load(path: PathBuf) -> std::io::Result<Instrument>{
+        let mut file = File::open(path)?;
+
+        let mut encoded_instrument = Vec::<u8>::new();
+        file.read_to_end(&mut encoded_instrument)?;
+
+        if let Some(versioned) = encoded_instrument.strip_prefix(RNI_MAGIC) {
+            let mut reader = versioned;
+            let version = read_u32(&mut reader)?;
+
+            if version != RNI_VERSION {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported .rni version {}", version)));
+            }
+
+            return bincode::deserialize(reader).map_err(|e| Error::new(ErrorKind::InvalidData, e));
+        }
+
+        match encoded_instrument.strip_prefix(FTI_MAGIC) {
+            Some(fti) => Instrument::from_fti(fti),
+            None => Err(Error::new(ErrorKind::InvalidData, "Not an .rni or .fti instrument")),
+        }
+    }
+
+    ///
+    /// Reads a 2A03 instrument from a FamiTracker .fti file, after the "FTI".
+    /// Relative arpeggios are converted to absolute ones where they can be,
+    /// other arpeggio settings are refused, and DPCM samples are skipped
+    ///
+    fn from_fti(mut reader: &[u8]) -> std::io::Result<Instrument>{
+        let version_text = read_bytes(&mut reader, 3)?;
+        let version = match version_text {
+            [major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit() => ((major - b'0') * 10 + (minor - b'0')) as u32,
+            _ => 0,
+        };
+        if !FTI_VERSIONS.contains(&version) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported .fti version {}", String::from_utf8_lossy(version_text))));
+        }
+
+        if read_u8(&mut reader)? != FTI_2A03 {
+            return Err(Error::new(ErrorKind::InvalidData, "Only 2A03 instruments can be imported"));
+        }
+
+        let name_length = read_u32(&mut reader)? as usize;
+        let name = String::from_utf8_lossy(read_bytes(&mut reader, name_length)?).into_owned();
+
+        let mut instrument = Instrument {
+            name,
+            sequences: Default::default(),
+        };
+
+        let sequence_count = read_u8(&mut reader)? as usize;
+        for i in 0 .. sequence_count {
+            if read_u8(&mut reader)? == 0 {
+                continue;
+            }
+
+            let count = read_u32(&mut reader)? as usize;
+            let loop_point = read_u32(&mut reader)? as i32;
+            let release_point = if version > 20 {read_u32(&mut reader)? as i32} else {-1};
+            let setting = if version >= 22 {read_u32(&mut reader)?} else {FTI_ARPEGGIO_ABSOLUTE};
+            let mut values: Vec<i8> = read_bytes(&mut reader, count)?.iter().map(|value| *value as i8).collect();
+
+            // Sequences past the five 2A03 ones are ignored
+            let sequence = match instrument.sequences.get_mut(i) {
+                Some(sequence) => sequence,
+                None => continue,
+            };
+
+            sequence.loop_point = usize::try_from(loop_point).ok().filter(|point| *point < count);
+
+            // FamiTracker waits on its release step, where the release part here
+            // starts on the step after it
+            sequence.release_point = usize::try_from(release_point + 1).ok().filter(|point| *point > 0 && *point < count);
+
+            if i == SequenceKind::Arpeggio as usize {
+                values = absolute_arpeggio(values, setting, sequence)?;
+            }
+            sequence.values = values;
+        }
+
+        Ok(instrument)
+    }
+
+    /// If releasing a note will play anything. Without a release point
+    /// in any sequence, a release ends the note straight away
+    pub fn has_release(&self) -> bool{
+        self.sequences.iter().any(|sequence| sequence.release_point.is_some())
+    }
+}
+
+///
+/// Turns the steps of an .fti arpeggio into absolute steps. A relative arpeggio
+/// becomes the running total of its steps, which only works if it plays through
+/// once, without a loop or a release point to wait on
+///
+fn absolute_arpeggio(values: Vec<i8>, setting: u32, sequence: &Sequence) -> std::io::Result<Vec<i8>>{
+    match setting {
+        FTI_ARPEGGIO_ABSOLUTE => Ok(values),
+        FTI_ARPEGGIO_RELATIVE => {
+            if sequence.loop_point.is_some() || sequence.release_point.is_some() {
+                return Err(Error::new(ErrorKind::InvalidData, "Relative arpeggios with a loop or release point can't be imported"));
+            }
+
+            let (min, max) = SequenceKind::Arpeggio.range();
+            let mut total = 0;
+            values.iter().map(|value| {
+                total += *value as i32;
+                if total < min as i32 || total > max as i32 {
+                    return Err(Error::new(ErrorKind::InvalidData, "The relative arpeggio goes out of range"));
+                }
+                Ok(total as i8)
+            }).collect()
+        },
+        FTI_ARPEGGIO_FIXED => Err(Error::new(ErrorKind::InvalidData, "Fixed arpeggios can't be imported")),
+        _ => Err(Error::new(ErrorKind::InvalidData, format!("Unsupported arpeggio setting {}", setting))),
+    }
+}
+
+fn read_bytes<'a>(reader: &mut &'a [u8], length: usize) -> std::io::Result<&'a [u8]>{
+    if reader.len() < length {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "The instrument file ended early"));
+    }
+
+    let (bytes, rest) = reader.split_at(length);
+    *reader = rest;
+    Ok(bytes)
+}
+
+fn read_u8(reader: &mut &[u8]) -> std::io::Result<u8>{
+    Ok(read_bytes(reader, 1)?[0])
+}
+
+fn read_u32(reader: &mut &[u8]) -> std::io::Result<u32>{
+    let bytes = read_bytes(reader, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+///
+/// Plays the sequences of an instrument for one note, a step every engine frame
+///
+#[derive(Clone, Debug, Default)]
+pub struct InstrumentPlayer{
+    positions: [usize; 5],
+    released: bool,
+    // The pitch sequences add up over time, rather than being set each frame
+    pitch_offset: i32,
+}
+
+impl InstrumentPlayer{
+    /// Restarts every sequence for a new note
+    pub fn trigger(&mut self, instrument: &Instrument){
+        *self = InstrumentPlayer::default();
+        self.add_pitch(instrument);
+    }
+
+    /// Moves every sequence on by one frame
+    pub fn tick(&mut self, instrument: &Instrument){
+        for (position, sequence) in self.positions.iter_mut().zip(&instrument.sequences) {
+            *position = sequence.next_position(*position, self.released);
+        }
+        self.add_pitch(instrument);
+    }
+
+    /// Jumps every sequence with a release point into its release part
+    pub fn release(&mut self, instrument: &Instrument){
+        self.released = true;
+
+        for (position, sequence) in self.positions.iter_mut().zip(&instrument.sequences) {
+            if let Some(release_point) = sequence.release_point {
+                *position = (*position).max(release_point);
+            }
+        }
+    }
+
+    pub fn is_released(&self) -> bool{
+        self.released
+    }
+
+    /// The current value of a sequence, or None if it is disabled
+    pub fn value(&self, instrument: &Instrument, kind: SequenceKind) -> Option<i8>{
+        let sequence = instrument.sequence(kind);
+        sequence.values.get(self.positions[kind as usize].min(sequence.values.len().saturating_sub(1))).copied()
+    }
+
+    /// How far the period has been moved by the pitch and hi-pitch sequences.
+    /// A positive offset lowers the note, as it does on the NES
+    pub fn pitch_offset(&self) -> i32{
+        self.pitch_offset
+    }
+
+    fn add_pitch(&mut self, instrument: &Instrument){
+        let pitch = self.value(instrument, SequenceKind::Pitch).unwrap_or(0) as i32;
+        let hi_pitch = self.value(instrument, SequenceKind::HiPitch).unwrap_or(0) as i32;
+
+        self.pitch_offset += pitch + hi_pitch * 16;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// Writes an instrument the way FamiTracker 2.4 saves a 2A03 .fti file,
+    /// with every sequence given the same arpeggio setting
+    fn fti_bytes(instrument: &Instrument, setting: u32) -> Vec<u8>{
+        let mut bytes = b"2.4".to_vec();
+        bytes.push(FTI_2A03);
+        bytes.extend((instrument.name.len() as u32).to_le_bytes());
+        bytes.extend(instrument.name.as_bytes());
+
+        bytes.push(instrument.sequences.len() as u8);
+        for sequence in &instrument.sequences {
+            if sequence.values.is_empty() {
+                bytes.push(0);
+                continue;
+            }
+
+            bytes.push(1);
+            bytes.extend((sequence.values.len() as u32).to_le_bytes());
+            bytes.extend(sequence.loop_point.map_or(-1, |point| point as i32).to_le_bytes());
+            bytes.extend(sequence.release_point.map_or(-1, |point| point as i32 - 1).to_le_bytes());
+            bytes.extend(setting.to_le_bytes());
+            bytes.extend(sequence.values.iter().map(|value| *value as u8));
+        }
+        bytes
+    }
+
+    fn test_instrument() -> Instrument{
+        let mut instrument = Instrument {
+            name: "Lead".to_owned(),
+            ..Default::default()
+        };
+
+        let volume = instrument.sequence_mut(SequenceKind::Volume);
+        volume.loop_point = Some(2);
+        volume.release_point = Some(5);
+
+        instrument.sequence_mut(SequenceKind::Arpeggio).values = vec![0, 4, 7, -12];
+        instrument.sequence_mut(SequenceKind::Pitch).values = vec![-3, 3];
+        instrument.sequence_mut(SequenceKind::Duty).values = vec![2];
+        instrument
+    }
+
+    #[test]
+    fn fti_round_trip(){
+        let instrument = test_instrument();
+        let imported = Instrument::from_fti(&fti_bytes(&instrument, FTI_ARPEGGIO_ABSOLUTE)).unwrap();
+
+        assert_eq!(imported, instrument);
+    }
+
+    #[test]
+    fn fti_relative_arpeggio_becomes_absolute(){
+        let mut instrument = Instrument::default();
+        instrument.sequence_mut(SequenceKind::Arpeggio).values = vec![0, 4, 3, -7];
+
+        let imported = Instrument::from_fti(&fti_bytes(&instrument, FTI_ARPEGGIO_RELATIVE)).unwrap();
+        assert_eq!(imported.sequence(SequenceKind::Arpeggio).values, vec![0, 4, 7, 0]);
+    }
+
+    #[test]
+    fn fti_refuses_arpeggios_it_cant_convert(){
+        let mut instrument = Instrument::default();
+        instrument.sequence_mut(SequenceKind::Arpeggio).values = vec![0, 4, 7];
+
+        assert!(Instrument::from_fti(&fti_bytes(&instrument, FTI_ARPEGGIO_FIXED)).is_err());
+
+        instrument.sequence_mut(SequenceKind::Arpeggio).loop_point = Some(0);
+        assert!(Instrument::from_fti(&fti_bytes(&instrument, FTI_ARPEGGIO_RELATIVE)).is_err());
+    }
+
+    #[test]
+    fn fti_older_versions_have_fewer_fields(){
+        // Version 2.1 has release points but no settings
+        let mut bytes = b"2.1".to_vec();
+        bytes.push(FTI_2A03);
+        bytes.extend(0u32.to_le_bytes());
+        bytes.push(1);
+        bytes.push(1);
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend((-1i32).to_le_bytes());
+        bytes.extend(0i32.to_le_bytes());
+        bytes.extend([15, 10, 5]);
+
+        let imported = Instrument::from_fti(&bytes).unwrap();
+        let volume = imported.sequence(SequenceKind::Volume);
+        assert_eq!(volume.values, vec![15, 10, 5]);
+        assert_eq!(volume.release_point, Some(1));
+    }
+
+    #[test]
+    fn fti_refuses_other_versions(){
+        let mut bytes = fti_bytes(&test_instrument(), FTI_ARPEGGIO_ABSOLUTE);
+        bytes[..3].copy_from_slice(b"3.0");
+
+        assert!(Instrument::from_fti(&bytes).is_err());
+    }
+
+    #[test]
+    fn rni_round_trip(){
+        let path = std::env::temp_dir().join("rustnes_rni_round_trip.rni");
+        let instrument = test_instrument();
+
+        instrument.save(path.clone()).unwrap();
+        let loaded = Instrument::load(path.clone());
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.unwrap(), instrument);
+    }
+}
@@ -0,0 +1,278 @@
+use egui::{Color32, Sense, Align2, FontId};
+
+use super::RustNES;
+use super::instrument::{Instrument, SequenceKind};
+
+// The most steps a sequence can have, the same as FamiTracker
+const MAX_SEQUENCE_LENGTH: usize = 252;
+
+const GRAPH_HEIGHT: f32 = 160.0;
+const MARKER_HEIGHT: f32 = 16.0;
+
+impl RustNES{
+    /// The window for creating instruments and drawing their sequences
+    pub(crate) fn instrument_editor(&mut self, ctx: &egui::Context){
+        let mut open = self.show_instrument_editor;
+
+        egui::Window::new("Instruments")
+        .open(&mut open)
+        .default_width(520.0)
+        .show(ctx, |ui| {
+            ui.horizontal_top(|ui| {
+                ui.vertical(|ui| {
+                    ui.set_width(150.0);
+                    self.instrument_list(ui);
+                });
+
+                ui.separator();
+
+                ui.vertical(|ui| {
+                    self.sequence_editor(ui);
+                });
+            });
+        });
+
+        self.show_instrument_editor = open;
+    }
+
+    /// The list of instruments, with buttons to add, duplicate, and delete them
+    fn instrument_list(&mut self, ui: &mut egui::Ui){
+        let instruments = &self.synth.project.instruments;
+
+        egui::ScrollArea::vertical().max_height(GRAPH_HEIGHT + 60.0).show(ui, |ui| {
+            for i in 0 .. instruments.len() {
+                if ui.selectable_label(self.selected_instrument == Some(i as u8), super::instrument_name(instruments, i)).clicked() {
+                    self.selected_instrument = Some(i as u8);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            // Notes can only store 256 different instruments
+            let can_add = self.synth.project.instruments.len() < 256;
+
+            if ui.add_enabled(can_add, egui::Button::new("New")).clicked() {
+                self.add_instrument(Instrument::default());
+            }
+
+            let selected = self.selected_instrument_index();
+            if ui.add_enabled(can_add && selected.is_some(), egui::Button::new("Duplicate")).clicked() {
+                if let Some(i) = selected {
+                    let mut copy = self.synth.project.instruments[i].clone();
+                    copy.name.push_str(" (copy)");
+                    self.add_instrument(copy);
+                }
+            }
+
+            if ui.add_enabled(selected.is_some(), egui::Button::new("Delete")).clicked() {
+                if let Some(i) = selected {
+                    self.synth.project.remove_instrument(i);
+                }
+                self.selected_instrument = None;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.synth.project.instruments.len() < 256, egui::Button::new("Import")).clicked() {
+                self.import_instruments();
+            }
+
+            let selected = self.selected_instrument_index().map(|i| &self.synth.project.instruments[i]);
+            if ui.add_enabled(selected.is_some(), egui::Button::new("Export")).clicked() {
+                if let Some(instrument) = selected {
+                    if let Some(path) = rfd::FileDialog::new()
+                        .add_filter("RustNES Instrument", &["rni"])
+                        .set_file_name(&format!("{}.rni", instrument.name))
+                        .save_file() {
+
+                        if let Err(e) = instrument.save(path) {
+                            println!("RustNES::instrument_list: Couldn't export instrument, {}", e);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Adds instruments from .rni or FamiTracker .fti files to the end of the list
+    fn import_instruments(&mut self){
+        let paths = rfd::FileDialog::new()
+            .add_filter("Instruments", &["rni", "fti"])
+            .pick_files()
+            .unwrap_or_default();
+
+        for path in paths {
+            if self.synth.project.instruments.len() >= 256 {
+                println!("RustNES::import_instruments: The instrument list is full");
+                break;
+            }
+
+            match Instrument::load(path) {
+                Ok(instrument) => self.add_instrument(instrument),
+                Err(e) => println!("RustNES::import_instruments: Couldn't import instrument, {}", e),
+            }
+        }
+    }
+
+    /// Adds an instrument to the end of the list, and selects it
+    pub(crate) fn add_instrument(&mut self, instrument: Instrument){
+        self.synth.project.instruments.push(instrument);
+        self.selected_instrument = Some((self.synth.project.instruments.len() - 1) as u8);
+    }
+
+    /// The index of the selected instrument, or None if it isn't in the instrument list
+    fn selected_instrument_index(&self) -> Option<usize>{
+        self.selected_instrument
+            .map(|i| i as usize)
+            .filter(|i| *i < self.synth.project.instruments.len())
+    }
+
+    /// Deselects the instrument if it is no longer in the instrument list,
+    /// so new notes don't point at a missing instrument
+    pub(crate) fn clamp_selected_instrument(&mut self){
+        if self.selected_instrument_index().is_none() {
+            self.selected_instrument = None;
+        }
+    }
+
+    /// The name, sequences, and preview of the selected instrument
+    fn sequence_editor(&mut self, ui: &mut egui::Ui){
+        let instrument_index = match self.selected_instrument_index() {
+            Some(i) => i,
+            None => {
+                ui.label("Select or create an instrument to edit it");
+                return;
+            },
+        };
+
+        let instrument = &mut self.synth.project.instruments[instrument_index];
+
+        ui.horizontal(|ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut instrument.name);
+        });
+
+        ui.horizontal(|ui| {
+            for kind in SequenceKind::ALL {
+                let label = if instrument.sequence(kind).values.is_empty() {kind.name().to_owned()} else {format!("{} •", kind.name())};
+                ui.selectable_value(&mut self.edited_sequence, kind, label);
+            }
+        });
+
+        let kind = self.edited_sequence;
+        let sequence = instrument.sequence_mut(kind);
+
+        ui.horizontal(|ui| {
+            ui.label("Length");
+            let mut length = sequence.values.len();
+            if ui.add(egui::DragValue::new(&mut length).clamp_range(0..=MAX_SEQUENCE_LENGTH)).changed() {
+                sequence.resize(length);
+            }
+            ui.label("Left click the markers to set the loop, right click for the release");
+        });
+
+        self.sequence_graph(ui, instrument_index, kind);
+        self.sequence_markers(ui, instrument_index, kind);
+
+        ui.horizontal(|ui| {
+            ui.label("Preview Row");
+            ui.add(egui::DragValue::new(&mut self.preview_row).clamp_range(0..=self.synth.get_playable_rows() - 1));
+
+            if ui.button("Preview").clicked() {
+                let instrument = &self.synth.project.instruments[instrument_index];
+                self.synth.preview_instrument(self.selected_channel, self.preview_row, instrument, 1.0);
+            }
+        });
+    }
+
+    /// Draws the steps of a sequence as bars, which can be drawn with the mouse
+    fn sequence_graph(&mut self, ui: &mut egui::Ui, instrument_index: usize, kind: SequenceKind){
+        let sequence = self.synth.project.instruments[instrument_index].sequence_mut(kind);
+        let (min, max) = kind.range();
+
+        let width = ui.available_width().max(200.0);
+        let (response, painter) = ui.allocate_painter(egui::vec2(width, GRAPH_HEIGHT), Sense::click_and_drag());
+        let rect = response.rect;
+
+        painter.rect_filled(rect, 0.0, Color32::from_rgb(30, 30, 30));
+
+        if sequence.values.is_empty() {
+            painter.text(rect.center(), Align2::CENTER_CENTER, "Disabled, set a length to use it", FontId::proportional(14.0), Color32::GRAY);
+            return;
+        }
+
+        let step_width = rect.width() / sequence.values.len() as f32;
+        let value_height = rect.height() / (max - min + 1) as f32;
+
+        // Bars grow out of 0, so negative values hang down from it
+        let zero_y = rect.bottom() - (0 - min) as f32 * value_height;
+
+        // Drawing sets the step under the pointer to the value at its height
+        if let Some(pos) = response.interact_pointer_pos() {
+            if rect.contains(pos) {
+                let step = ((pos.x - rect.left()) / step_width) as usize;
+                let value = min as f32 + ((rect.bottom() - pos.y) / value_height).floor();
+
+                if let Some(step_value) = sequence.values.get_mut(step) {
+                    *step_value = (value as i8).clamp(min, max);
+                }
+            }
+        }
+
+        for (step, value) in sequence.values.iter().enumerate() {
+            let left = rect.left() + step as f32 * step_width;
+            let top = if *value >= 0 {zero_y - (*value as f32 + 1.0) * value_height} else {zero_y};
+            let bottom = if *value >= 0 {zero_y} else {zero_y - *value as f32 * value_height};
+
+            let in_release = matches!(sequence.release_point, Some(release_point) if step >= release_point);
+            let color = if in_release {self.release_color} else {self.selected_color};
+
+            let bar = egui::Rect::from_min_max(egui::pos2(left + 1.0, top), egui::pos2(left + step_width - 1.0, bottom));
+            painter.rect_filled(bar, 0.0, color);
+
+            if step_width > 14.0 {
+                painter.text(egui::pos2(left + step_width / 2.0, rect.top() + 2.0), Align2::CENTER_TOP, value.to_string(), FontId::monospace(10.0), Color32::WHITE);
+            }
+        }
+    }
+
+    /// The strip under the graph that shows and sets the loop and release points
+    fn sequence_markers(&mut self, ui: &mut egui::Ui, instrument_index: usize, kind: SequenceKind){
+        let sequence = self.synth.project.instruments[instrument_index].sequence_mut(kind);
+
+        let width = ui.available_width().max(200.0);
+        let (response, painter) = ui.allocate_painter(egui::vec2(width, MARKER_HEIGHT), Sense::click());
+        let rect = response.rect;
+
+        painter.rect_filled(rect, 0.0, self.unselected_color);
+
+        if sequence.values.is_empty() {
+            return;
+        }
+
+        let step_width = rect.width() / sequence.values.len() as f32;
+
+        if let Some(pos) = response.interact_pointer_pos() {
+            let step = (((pos.x - rect.left()) / step_width) as usize).min(sequence.values.len() - 1);
+
+            // Clicking the step a point is already on removes it
+            if response.clicked() {
+                sequence.loop_point = if sequence.loop_point == Some(step) {None} else {Some(step)};
+            }
+            if response.secondary_clicked() {
+                sequence.release_point = if sequence.release_point == Some(step) {None} else {Some(step)};
+            }
+        }
+
+        let markers = [(sequence.loop_point, "L", self.hold_color), (sequence.release_point, "R", self.release_color)];
+        for (point, label, color) in markers {
+            if let Some(step) = point {
+                let left = rect.left() + step as f32 * step_width;
+                let marker = egui::Rect::from_min_size(egui::pos2(left, rect.top()), egui::vec2(step_width.max(12.0), rect.height()));
+
+                painter.rect_filled(marker, 0.0, color);
+                painter.text(marker.center(), Align2::CENTER_CENTER, label, FontId::monospace(11.0), Color32::WHITE);
+            }
+        }
+    }
+}
@@ -7,69 +7,90 @@ use bincode;
 use serde::{Serialize, Deserialize};
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::{Error, ErrorKind};
 
 // Atomics are used to stop the rodio play thread
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use crate::rustnes::waves;
+use crate::rustnes::export;
+use crate::rustnes::timing::{self, TimingMode, Region};
+use crate::rustnes::instrument::Instrument;
+use crate::rustnes::effect::Effect;
+
+// Every .rsf file starts with these bytes followed by the format version.
+// Files without them were saved before the format was versioned.
+// Any change to the saved layout needs a new version, and load_project
+// needs a migration from every earlier version, so older files still open
+const RSF_MAGIC: &[u8; 4] = b"RSF\0";
+const RSF_VERSION: u32 = 1;
+
+// Legacy files stored the bottom row of the stepper as note 0, which played this note of the period table
+const LEGACY_NOTE_OFFSET: u32 = 36;
+
+// The loudest a column's volume can be, the same as the NES's 4 bit volume
+pub(crate) const MAX_VOLUME: u8 = 15;
 
 /// The struct that defines all of the synth values
 /// TODO make more of these variables private, and add getters and setters
 pub struct Synth{
     //pub inital_size: usize,
     
-    pub track: Track,
-    pub tempo: f32,
+    pub project: Project,
+    /// The index of the song being edited and played
+    pub song: usize,
     pub volume: f32,
 
-    pub notes_per_measure: u32,
-    pub measures_per_page: u32,
-
     pub rows_per_column: u32,
 
+    /// The mute and solo of each channel, shared with the play thread
+    pub mix: Arc<waves::ChannelMix>,
+
     stop_thread: Arc<AtomicBool>,
 }
 
 impl Default for Synth{
     fn default() -> Self {
-
-        // The inital size of the window/measure
-        // TODO make this more editable/ getters setters
-        Synth::new(8, 4, 4)
+        Synth::new()
     }
 }
 
 impl Synth{
-    pub fn new(max_pages: u32, notes_per_measure: u32, measures_per_page: u32) -> Self{
+    pub fn new() -> Self{
         Self { 
-            //inital_size: initial_size as usize,
-            track: Track::new(max_pages as usize * notes_per_measure as usize * 4),
-
-            // The tempo is set to 960, as a note is technically only 1/16
-            tempo: 960.0,
+            project: Project::default(),
+            song: 0,
             volume: 100.0,
 
-            notes_per_measure: notes_per_measure,
-            measures_per_page: measures_per_page as u32,
-
             rows_per_column: 24,
 
+            mix: Arc::new(waves::ChannelMix::default()),
+
             stop_thread: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// The song being edited
+    pub fn track(&self) -> &Track{
+        &self.project.songs[self.song]
+    }
+
+    pub fn track_mut(&mut self) -> &mut Track{
+        &mut self.project.songs[self.song]
+    }
+
     pub fn play(&mut self){
 
         // Stop any threads that are playing
         self.stop();
 
-        let sliced_track = Track::slice_new(0, &self.track);
+        let sliced_track = Track::slice_new(0, self.track());
 
         // The length that the sound should play for
-        let length = self.track.get_length() as f32 * self.get_beats_per_second();
+        let length = sliced_track.get_duration();
 
-        let source = waves::Oscillators::new(&sliced_track, self.tempo)
+        let source = waves::Oscillators::new(&sliced_track, &self.project.instruments, self.mix.clone())
             .take_duration(Duration::from_secs_f32(length))
             .amplify(self.volume / 100.0);
 
@@ -113,13 +134,74 @@ impl Synth{
         
     }
 
-    pub fn new_track(&mut self){
-        self.track = Track::new(self.track.get_length() as usize);
+    /// Plays a single note with an instrument for length seconds, releasing it
+    /// halfway through so the release part of the instrument can be heard
+    pub fn preview_instrument(&self, selected_channel: usize, row_index: u32, instrument: &Instrument, length: f32){
+        let mut track = Track::new(1);
+
+        // Each of the 2 columns lasts half of the length
+        track.bpm = ((30.0 / length) as u32).max(1);
+        track.rows_per_beat = 4;
+        track.speed = timing::DEFAULT_SPEED;
+        track.region = self.track().region;
+
+        let channel = selected_channel.min(3);
+        if let Some(column) = track.get_column_mut(0, channel) {
+            column.select(row_index);
+            column.set_instrument(Some(0));
+        }
+        if let Some(column) = track.get_column_mut(1, channel) {
+            column.release();
+        }
+
+        // The preview is always heard, even if its channel is muted
+        let source = waves::Oscillators::new(&track, std::slice::from_ref(instrument), Arc::new(waves::ChannelMix::default()))
+            .take_duration(Duration::from_secs_f32(length))
+            .amplify(self.volume / 100.0);
+
+        thread::spawn(move ||{
+            let (_stream, stream_handle) = OutputStream::try_default().unwrap();
+            let sink = Sink::try_new(&stream_handle).unwrap();
+
+            sink.append(source);
+            sink.sleep_until_end();
+        });
+    }
+
+    pub fn new_project(&mut self){
+        self.project = Project::default();
+        self.song = 0;
+    }
+
+    /// How many rows the stepper can scroll through, one for each note of the period table
+    pub fn get_playable_rows(&self) -> u32{
+        waves::NOTE_COUNT as u32
+    }
+
+    /// Adds a new song to the end of the project, and selects it
+    pub fn add_song(&mut self){
+        let track = Track {
+            title: format!("Song {}", self.project.songs.len() + 1),
+            ..Track::default()
+        };
+
+        self.project.songs.push(track);
+        self.song = self.project.songs.len() - 1;
+    }
+
+    /// Removes the selected song. The last song of a project can't be removed
+    pub fn remove_song(&mut self){
+        if self.project.songs.len() <= 1 {
+            println!("Synth::remove_song: A project needs at least one song");
+            return;
+        }
+
+        self.project.songs.remove(self.song);
+        self.song = self.song.min(self.project.songs.len() - 1);
     }
 
     pub fn add_page(&mut self, amount: usize) -> bool{
-        self.track.page_count += amount as u32;
-        self.track.add_columns(amount * self.get_notes_per_page() as usize);
+        self.track_mut().add_pages(amount);
         true
     }
 
@@ -129,67 +211,274 @@ impl Synth{
             return false;
         }
 
-        self.track.page_count -= amount as u32;
-        self.track.remove_columns(amount * self.get_notes_per_page() as usize);
+        self.track_mut().remove_pages(amount);
         true
     }
 
     pub fn can_remove_measure(&self, amount: usize) -> bool {
-        (self.track.get_length() as u32) > amount as u32 * self.get_notes_per_page()
-    }
-
-    pub fn get_beats_per_second(&self) -> f32 {
-        self.tempo / 60.0
+        self.track().get_page_count() > amount
     }
 
+    /// Gets a column of the song, from the pattern its page plays on the channel.
+    /// Patterns used on more than one page are changed everywhere they are used
     pub fn get_channel_column(&mut self, column_index: usize, selected_channel: usize) -> Option<&mut WaveColumn>{
-        if selected_channel >= self.track.channels.len() {
+        if selected_channel >= self.track().get_channel_count() {
             println!("Synth::get_channel_column: selected_channel {} out of bounds", selected_channel);
             return None;
         }
-        let current_channel = &mut self.track.channels[selected_channel];
 
-        if column_index >= current_channel.len(){
+        if column_index >= self.track().get_length(){
             println!("Synth::get_channel_column: column_index {} out of bounds", column_index);
             return None;
         }
 
-        Some(&mut current_channel[column_index])
+        self.track_mut().get_column_mut(column_index, selected_channel)
     }
 
-    pub fn save_track(&self, path: PathBuf) -> std::io::Result<()> {
-        let encoded_track: Vec<u8> = bincode::serialize(&self.track).unwrap();
+    /// Saves the project as an .rsf file. The file starts with RSF_MAGIC and
+    /// the format version so that older layouts can still be recognized
+    pub fn save_project(&self, path: PathBuf) -> std::io::Result<()> {
+        let encoded_track: Vec<u8> = bincode::serialize(&self.project)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
         let mut file = File::create(path)?;
+        file.write_all(RSF_MAGIC)?;
+        file.write_all(&RSF_VERSION.to_le_bytes())?;
         file.write_all(&encoded_track)
     }
 
-    pub fn load_track(&mut self, path: PathBuf) -> std::io::Result<()> {
+    /// Loads an .rsf file. Files saved before the format was versioned only
+    /// contain the channels and page count, and become a project with one song
+    /// with the default timing
+    pub fn load_project(&mut self, path: PathBuf) -> std::io::Result<()> {
         let mut file = File::open(path)?;
 
         let mut encoded_track = Vec::<u8>::new();
         file.read_to_end(&mut encoded_track)?;
 
-        let decoded_track: Track = bincode::deserialize(&encoded_track[..]).unwrap();
+        let decoded_track = match encoded_track.strip_prefix(RSF_MAGIC) {
+            Some(versioned) => {
+                if versioned.len() < 4 {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "Missing .rsf version"));
+                }
 
-        self.track = decoded_track;
-        
-        // TODO make this not just send an Ok
+                let (version, body) = versioned.split_at(4);
+                let version = u32::from_le_bytes([version[0], version[1], version[2], version[3]]);
+
+                // Migrations from older versions go here, converting them into the current Project
+                match version {
+                    RSF_VERSION => bincode::deserialize::<Project>(body),
+                    _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unsupported .rsf version {}", version))),
+                }
+            },
+            None => bincode::deserialize::<LegacyTrack>(&encoded_track[..]).map(Project::from),
+        };
+
+        let mut decoded_project = decoded_track.map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if decoded_project.songs.is_empty() {
+            decoded_project.songs.push(Track::default());
+        }
+        for track in &mut decoded_project.songs {
+            track.fit_to_pages();
+        }
+
+        self.project = decoded_project;
+        self.song = 0;
         Ok(())
     }
 
+    /// Renders the selected song into a .wav file, at the current volume.
+    /// Muted channels are only left out if use_mix is set
+    pub fn export_wav(&self, path: PathBuf, use_mix: bool) -> std::io::Result<()> {
+        let mix = if use_mix {self.mix.clone()} else {Arc::new(waves::ChannelMix::default())};
+        export::export_wav(self.track(), &self.project.instruments, self.volume / 100.0, mix, path)
+    }
+
+    /// Saves every song of the project into a .nsf file as subtunes.
+    /// Muted channels are only left out if use_mix is set
+    pub fn export_nsf(&self, path: PathBuf, use_mix: bool) -> std::io::Result<()> {
+        let mix = if use_mix {self.mix.clone()} else {Arc::new(waves::ChannelMix::default())};
+        export::export_nsf(&self.project.songs, &self.project.instruments, mix, path)
+    }
+
+    /// Saves the selected song into a .vgm file.
+    /// Muted channels are only left out if use_mix is set
+    pub fn export_vgm(&self, path: PathBuf, use_mix: bool) -> std::io::Result<()> {
+        let mix = if use_mix {self.mix.clone()} else {Arc::new(waves::ChannelMix::default())};
+        export::export_vgm(self.track(), &self.project.instruments, mix, path)
+    }
+
     pub fn get_notes_per_page(&self) -> u32{
-        self.notes_per_measure * self.measures_per_page
+        self.track().get_rows_per_page()
+    }
+}
+
+///
+/// Everything saved in an .rsf file. A project holds a list of songs that share
+/// the same instruments, like the songs of a game's soundtrack
+///
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Project{
+    pub(crate) songs: Vec<Track>,
+    pub(crate) instruments: Vec<Instrument>,
+}
+
+impl Default for Project{
+    fn default() -> Self {
+        Self {
+            songs: vec![Track::default()],
+            instruments: Vec::new(),
+        }
     }
 }
 
-/// The current track of the synth
-/// contains the 4 main channels
-#[derive(Serialize, Deserialize, Debug)]
+impl From<LegacyTrack> for Project{
+    fn from(legacy: LegacyTrack) -> Self {
+        Self {
+            songs: vec![Track::from(legacy)],
+            ..Project::default()
+        }
+    }
+}
+
+impl Project{
+    /// Removes an instrument. Notes using it are played without an instrument,
+    /// and notes using a later instrument are moved down to keep the same sound.
+    /// Every song is updated, as they all share the instruments
+    pub fn remove_instrument(&mut self, instrument_index: usize){
+        if instrument_index >= self.instruments.len() {
+            println!("Project::remove_instrument: instrument_index {} out of bounds", instrument_index);
+            return;
+        }
+        self.instruments.remove(instrument_index);
+
+        for track in &mut self.songs {
+            for column in track.patterns.iter_mut().flatten().flatten() {
+                match column.get_instrument() {
+                    Some(instrument) if instrument as usize == instrument_index => column.set_instrument(None),
+                    Some(instrument) if instrument as usize > instrument_index => column.set_instrument(Some(instrument - 1)),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// The columns one channel plays for one page of the song
+pub type Pattern = Vec<WaveColumn>;
+
+/// One song of the project
+/// contains the patterns of the 4 main channels, the order they are played in,
+/// and the timing of the song
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Track{
-    pub(crate) channels: [Vec<WaveColumn>; 4],
-    pub(crate) page_count: u32,
+    // The song's details, written into exported files
+    pub(crate) title: String,
+    pub(crate) author: String,
+    pub(crate) copyright: String,
+    pub(crate) comments: String,
+
+    /// The patterns of each channel. A pattern can be played on any number of pages
+    pub(crate) patterns: [Vec<Pattern>; 4],
+    /// The order list, like FamiTracker's frames. Each entry is a page of the
+    /// song, with the pattern each channel plays on it
+    pub(crate) orders: Vec<[usize; 4]>,
+
+    /// Beats per minute, where a beat is one beat_unit note spread over rows_per_beat columns
+    pub(crate) bpm: u32,
+    /// Engine frames per column at the starting tempo, like FamiTracker's speed
+    pub(crate) speed: u32,
+    /// How many columns make up one beat
+    pub(crate) rows_per_beat: u32,
+
+    pub(crate) timing_mode: TimingMode,
+    pub(crate) region: Region,
+
+    // The time signature, beats_per_measure / beat_unit. The beat unit only names the note
+    // a beat is written as, since how long a beat lasts is set by the bpm and rows_per_beat
+    pub(crate) beats_per_measure: u32,
+    pub(crate) beat_unit: u32,
+
+    pub(crate) measures_per_page: u32,
+
+    /// The tempo automation lane. Has one entry for every column of the song,
+    /// so it stays with the page rather than the pattern
+    pub(crate) tempo_lane: Vec<Option<TempoEvent>>,
+
+    pub(crate) grooves: Vec<Groove>,
+    /// The groove used from the start of the song, if any
+    pub(crate) groove: Option<usize>,
+}
+
+/// A change in tempo at the start of a column, like a tracker Fxx command
+/// Changing the speed also turns off any groove, as it does in FamiTracker
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum TempoEvent{
+    Bpm(u32),
+    Speed(u32),
+    Groove(usize),
+}
+
+/// A repeating list of how many frames each column lasts.
+/// Used for swing and shuffle, like FamiTracker's grooves
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Groove{
+    pub(crate) name: String,
+    pub(crate) frames: Vec<u32>,
 }
 
+impl Default for Groove{
+    fn default() -> Self {
+        Self {
+            name: "Swing".to_owned(),
+            frames: vec![8, 4],
+        }
+    }
+}
+
+/// The track layout used before .rsf files were versioned
+#[derive(Deserialize)]
+struct LegacyTrack{
+    channels: [Vec<LegacyWaveColumn>; 4],
+    page_count: u32,
+}
+
+/// Legacy columns only had a pitch, and every note was a note on.
+/// The pitch was stored as a single bit, so only 32 notes could be used
+#[derive(Deserialize)]
+struct LegacyWaveColumn{
+    column: u32,
+}
+
+// Legacy tracks were always played at 960 columns per minute
+// with 32 columns per page, which the default timing matches.
+// Every page of a legacy track becomes its own pattern
+impl From<LegacyTrack> for Track{
+    fn from(legacy: LegacyTrack) -> Self {
+        let mut track = Track::new(0);
+        track.set_channels(legacy.channels.map(|channel| channel.into_iter().map(WaveColumn::from).collect()));
+
+        let missing_pages = (legacy.page_count as usize).saturating_sub(track.get_page_count());
+        track.add_pages(missing_pages);
+        track
+    }
+}
+
+impl From<LegacyWaveColumn> for WaveColumn{
+    fn from(legacy: LegacyWaveColumn) -> Self {
+        let note = Some(legacy.column.trailing_zeros())
+            .filter(|row| *row < 32)
+            .map(|row| note_index(row + LEGACY_NOTE_OFFSET));
+
+        Self {
+            note,
+            event: if note.is_none() {NoteEvent::Empty} else {NoteEvent::On},
+            instrument: None,
+            volume: None,
+            effect: None,
+        }
+    }
+}
 
 // Just initializing all 4 channels in an array  
 // Index 0: Pulse one
@@ -198,88 +487,585 @@ pub struct Track{
 // Index 3: Noise
 impl Default for Track{
     fn default() -> Self {
-        Track::new(8)
+        Track::new(4)
     }
 }
 
 impl Track{
 
-    pub fn new(initial_size: usize) -> Self{
-        Self {  
-            channels: [vec![WaveColumn::default(); initial_size], 
-            vec![WaveColumn::default(); initial_size], 
-            vec![WaveColumn::default(); initial_size], 
-            vec![WaveColumn::default(); initial_size]],
-            page_count: 4,
-        }
+    /// Creates a track with page_count pages, each with new empty patterns
+    pub fn new(page_count: usize) -> Self{
+        let mut track = Self {  
+            title: "New Song".to_owned(),
+            author: String::new(),
+            copyright: String::new(),
+            comments: String::new(),
+
+            patterns: Default::default(),
+            orders: Vec::new(),
+
+            bpm: 240,
+            speed: timing::DEFAULT_SPEED,
+            rows_per_beat: 4,
+
+            timing_mode: TimingMode::RealTime,
+            region: Region::Ntsc,
+            beats_per_measure: 4,
+            beat_unit: 4,
+            measures_per_page: 2,
+
+            tempo_lane: Vec::new(),
+
+            grooves: Vec::new(),
+            groove: None,
+        };
+        track.add_pages(page_count);
+        track
     }
 
+    /// A copy of the track from column start onwards, with the order list
+    /// played out so that every page has its own patterns
     pub fn slice_new(start: usize, track: &Track) -> Self{
-        let initial_size = track.get_length() - start;
-
-        let mut sliced_track = Self {  
-            channels: [vec![WaveColumn::default(); initial_size], 
-            vec![WaveColumn::default(); initial_size], 
-            vec![WaveColumn::default(); initial_size], 
-            vec![WaveColumn::default(); initial_size]],
-            page_count: 4,
+        let mut sliced_track = Self {
+            title: track.title.to_owned(),
+            author: track.author.to_owned(),
+            copyright: track.copyright.to_owned(),
+            comments: track.comments.to_owned(),
+            bpm: track.get_bpm_at(start),
+            speed: track.get_speed_at(start),
+            rows_per_beat: track.rows_per_beat,
+            timing_mode: track.timing_mode,
+            region: track.region,
+            beats_per_measure: track.beats_per_measure,
+            beat_unit: track.beat_unit,
+            measures_per_page: track.measures_per_page,
+            grooves: track.grooves.to_vec(),
+            groove: track.get_groove_at(start),
+            ..Track::new(0)
         };
 
-        for i in 0 .. sliced_track.channels.len(){
-            sliced_track.channels[i] = track.channels[i].to_vec();
-        }
+        sliced_track.tempo_lane = track.tempo_lane[start..].to_vec();
+        sliced_track.set_channels([0, 1, 2, 3].map(|channel| track.channel_columns(channel)[start..].to_vec()));
 
         sliced_track
     }
 
+    /// Replaces the song with whole channels of columns, cutting them into
+    /// a new pattern for every page. The last page is filled out with empty columns
+    pub fn set_channels(&mut self, channels: [Vec<WaveColumn>; 4]){
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let longest = channels.iter().map(|channel| channel.len()).max().unwrap_or(0);
+        let page_count = longest.div_ceil(rows_per_page).max(1);
+
+        for (patterns, channel) in self.patterns.iter_mut().zip(channels) {
+            *patterns = channel.chunks(rows_per_page).map(|chunk| chunk.to_vec()).collect();
+            patterns.resize(page_count, Pattern::new());
+
+            for pattern in patterns.iter_mut() {
+                pattern.resize(rows_per_page, WaveColumn::default());
+            }
+        }
+
+        self.orders = (0 .. page_count).map(|page| [page; 4]).collect();
+        self.tempo_lane.resize(page_count * rows_per_page, None);
+    }
+
+    /// The column at column_index of the song on a channel, from the pattern its page plays
+    pub fn get_column(&self, column_index: usize, channel: usize) -> Option<&WaveColumn>{
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let pattern = self.orders.get(column_index / rows_per_page)?[channel];
+
+        self.patterns.get(channel)?.get(pattern)?.get(column_index % rows_per_page)
+    }
+
+    pub fn get_column_mut(&mut self, column_index: usize, channel: usize) -> Option<&mut WaveColumn>{
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let pattern = self.orders.get(column_index / rows_per_page)?[channel];
+
+        self.patterns.get_mut(channel)?.get_mut(pattern)?.get_mut(column_index % rows_per_page)
+    }
+
+    /// Every column a channel plays from start to end, following the order list
+    pub fn channel_columns(&self, channel: usize) -> Vec<WaveColumn>{
+        self.orders.iter()
+            .flat_map(|order| self.patterns[channel][order[channel]].iter().cloned())
+            .collect()
+    }
+
+    /// Sets the pattern a channel plays on a page.
+    /// A pattern index one past the last pattern creates a new empty pattern
+    pub fn set_order(&mut self, page: usize, channel: usize, pattern: usize){
+        if page >= self.orders.len() || pattern > self.patterns[channel].len() {
+            println!("Track::set_order: page {} or pattern {} out of bounds", page, pattern);
+            return;
+        }
+
+        if pattern == self.patterns[channel].len() {
+            self.new_pattern(channel, None);
+        }
+        self.orders[page][channel] = pattern;
+    }
+
+    /// Adds a pattern to a channel, either empty or a copy of another pattern.
+    /// Returns the index of the new pattern
+    pub fn new_pattern(&mut self, channel: usize, copy_of: Option<usize>) -> usize{
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let patterns = &mut self.patterns[channel];
+
+        let pattern = match copy_of.and_then(|pattern| patterns.get(pattern)) {
+            Some(pattern) => pattern.clone(),
+            None => vec![WaveColumn::default(); rows_per_page],
+        };
+
+        patterns.push(pattern);
+        patterns.len() - 1
+    }
+
+    /// How many pages a channel plays a pattern on
+    pub fn get_pattern_uses(&self, channel: usize, pattern: usize) -> usize{
+        self.orders.iter().filter(|order| order[channel] == pattern).count()
+    }
+
+    /// The tempo in effect at column_index, after every tempo event before it
+    pub fn get_bpm_at(&self, column_index: usize) -> u32{
+        self.tempo_lane.iter()
+            .take(column_index + 1)
+            .rev()
+            .find_map(|event| match event {
+                Some(TempoEvent::Bpm(bpm)) => Some(*bpm),
+                _ => None,
+            })
+            .unwrap_or(self.bpm)
+    }
+
+    /// The speed in effect at column_index, after every speed event before it
+    pub fn get_speed_at(&self, column_index: usize) -> u32{
+        self.tempo_lane.iter()
+            .take(column_index + 1)
+            .rev()
+            .find_map(|event| match event {
+                Some(TempoEvent::Speed(speed)) => Some(*speed),
+                _ => None,
+            })
+            .unwrap_or(self.speed)
+    }
+
+    /// The groove in effect at column_index. Speed changes turn grooves off
+    pub fn get_groove_at(&self, column_index: usize) -> Option<usize>{
+        self.tempo_lane.iter()
+            .take(column_index + 1)
+            .rev()
+            .find_map(|event| match event {
+                Some(TempoEvent::Groove(groove)) => Some(Some(*groove)),
+                Some(TempoEvent::Speed(_)) => Some(None),
+                _ => None,
+            })
+            .unwrap_or(self.groove)
+    }
+
+    /// Removes a groove, along with any tempo events that use it.
+    /// Events using a later groove are moved down to keep the same groove
+    pub fn remove_groove(&mut self, groove_index: usize){
+        if groove_index >= self.grooves.len() {
+            println!("Track::remove_groove: groove_index {} out of bounds", groove_index);
+            return;
+        }
+        self.grooves.remove(groove_index);
+
+        let shift = |groove: usize| match groove {
+            g if g == groove_index => None,
+            g if g > groove_index => Some(g - 1),
+            g => Some(g),
+        };
+
+        self.groove = self.groove.and_then(shift);
+
+        for event in &mut self.tempo_lane {
+            if let Some(TempoEvent::Groove(groove)) = event {
+                *event = shift(*groove).map(TempoEvent::Groove);
+            }
+        }
+    }
+
+    /// The length of the whole track in seconds, following the tempo lane
+    pub fn get_duration(&self) -> f32{
+        timing::RowClock::new(self).get_duration()
+    }
+
+    /// How many beats are shown on one page of the stepper
+    pub fn get_beats_per_page(&self) -> u32{
+        self.beats_per_measure * self.measures_per_page
+    }
+
+    pub fn get_rows_per_page(&self) -> u32{
+        self.rows_per_beat * self.get_beats_per_page()
+    }
+
+    /// Changes the rows per beat, beats per measure, and measures per page, laying the whole
+    /// song out again across pages of the new size so no note or tempo change is lost.
+    /// Pages are added for anything that no longer fits, and each page gets its own patterns
+    pub fn set_page_layout(&mut self, rows_per_beat: u32, beats_per_measure: u32, measures_per_page: u32){
+        let old_rows_per_page = self.get_rows_per_page();
+        let old_length = self.get_length();
+        let channels = [0, 1, 2, 3].map(|channel| self.channel_columns(channel));
+
+        self.rows_per_beat = rows_per_beat;
+        self.beats_per_measure = beats_per_measure;
+        self.measures_per_page = measures_per_page;
+
+        let rows_per_page = self.get_rows_per_page() as usize;
+        if rows_per_page == old_rows_per_page as usize {
+            return;
+        }
+
+        // The column after the last one with anything in it
+        let used = channels.iter()
+            .filter_map(|columns| columns.iter().rposition(|column| *column != WaveColumn::default()))
+            .chain(self.tempo_lane.iter().rposition(|event| event.is_some()))
+            .max()
+            .map_or(0, |last| last + 1);
+
+        // The song keeps as close to its old length as whole pages allow, rounding halves down,
+        // so changing the layout and back again doesn't keep adding empty pages
+        let nearest = (2 * old_length + rows_per_page - 1) / (2 * rows_per_page);
+        let page_count = used.div_ceil(rows_per_page).max(nearest).max(1);
+        let length = page_count * rows_per_page;
+
+        self.set_channels(channels.map(|mut columns| {
+            columns.resize(length, WaveColumn::default());
+            columns
+        }));
+    }
+
+    /// Resizes every pattern to a full page, after loading a song
+    pub fn fit_to_pages(&mut self){
+        if self.orders.is_empty() {
+            self.add_pages(1);
+        }
+
+        let rows_per_page = self.get_rows_per_page() as usize;
+
+        for (channel, patterns) in self.patterns.iter_mut().enumerate() {
+            // Orders that point past the last pattern are given empty patterns
+            let used = self.orders.iter().map(|order| order[channel] + 1).max().unwrap_or(0);
+            if patterns.len() < used {
+                patterns.resize(used, Pattern::new());
+            }
+
+            for pattern in patterns.iter_mut() {
+                pattern.resize(rows_per_page, WaveColumn::default());
+            }
+        }
+
+        // The tempo lane is resized one page at a time like the patterns, so every
+        // tempo change stays on the same page as its notes
+        let page_count = self.orders.len();
+        let old_rows_per_page = self.tempo_lane.len() / page_count;
+
+        if old_rows_per_page > 0 && self.tempo_lane.len().is_multiple_of(page_count) {
+            let pages: Vec<Vec<Option<TempoEvent>>> = self.tempo_lane.chunks(old_rows_per_page).map(|page| page.to_vec()).collect();
+            self.tempo_lane = pages.into_iter()
+                .flat_map(|mut page| {
+                    page.resize(rows_per_page, None);
+                    page
+                })
+                .collect();
+        }
+        else {
+            self.tempo_lane.resize(self.get_length(), None);
+        }
+    }
+
     /// Gets the amount of notes in the track
-    /// This length is based on the len() of the vec in index 0
-    /// As all of the channels are the same length, this should be fine
+    /// Every page is the same length, so this is the pages times the rows per page
     pub fn get_length(&self) -> usize{
-        self.channels[0].len()
+        self.orders.len() * self.get_rows_per_page() as usize
+    }
+
+    /// The number of pages in the order list
+    pub fn get_page_count(&self) -> usize{
+        self.orders.len()
     }
 
     /// Gets the current number of the channels
     /// Default will be 2 pulse, 1 triangle, and 1 noise. 4 in total
     pub fn get_channel_count(&self) -> usize{
-        self.channels.len()
+        self.patterns.len()
     }
 
-    /// Add columns based "amount"
-    pub fn add_columns(&mut self, amount: usize){
-        for channel in &mut self.channels{
-            let new_columns = vec![WaveColumn::default(); amount];
-            channel.extend_from_slice(&new_columns);
+    /// Adds "amount" pages to the end of the song, each with new empty patterns
+    pub fn add_pages(&mut self, amount: usize){
+        for _ in 0 .. amount {
+            let order = [0, 1, 2, 3].map(|channel| self.new_pattern(channel, None));
+            self.orders.push(order);
         }
+        self.tempo_lane.resize(self.get_length(), None);
     }
 
-    /// Remove columns based on "amount"
-    /// If any of the channels have a length < amount, return
-    pub fn remove_columns(&mut self, amount: usize) {
-        if self.channels.iter().any(|channel| channel.len() <= amount){
-            println!("Track::remove_columns: Can't remove any more columns");
+    /// Removes "amount" pages from the end of the song
+    /// Patterns at the end that are no longer used by any page are removed too
+    pub fn remove_pages(&mut self, amount: usize) {
+        if self.orders.len() <= amount {
+            println!("Track::remove_pages: Can't remove any more pages");
             return
         }
 
-        self.channels.iter_mut().for_each(|channel|{
-            channel.truncate(channel.len() - amount);
-        });
+        self.orders.truncate(self.orders.len() - amount);
+        self.tempo_lane.truncate(self.get_length());
+        self.remove_unused_patterns();
+    }
+
+    /// Removes patterns at the end of each channel that no page plays
+    fn remove_unused_patterns(&mut self){
+        for channel in 0 .. self.patterns.len() {
+            while let Some(last) = self.patterns[channel].len().checked_sub(1) {
+                if self.get_pattern_uses(channel, last) > 0 {
+                    break;
+                }
+                self.patterns[channel].pop();
+            }
+        }
+    }
+
+    /// Inserts a page with new empty patterns before page_index, moving every later page back
+    pub fn insert_page(&mut self, page_index: usize){
+        if page_index > self.orders.len() {
+            println!("Track::insert_page: page_index {} out of bounds", page_index);
+            return;
+        }
 
+        let order = [0, 1, 2, 3].map(|channel| self.new_pattern(channel, None));
+        self.orders.insert(page_index, order);
+
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let start = page_index * rows_per_page;
+        self.tempo_lane.splice(start .. start, vec![None; rows_per_page]);
+    }
+
+    /// Removes the page at page_index, moving every later page forward.
+    /// The last page of a song can't be removed
+    pub fn delete_page(&mut self, page_index: usize){
+        if page_index >= self.orders.len() || self.orders.len() <= 1 {
+            println!("Track::delete_page: Can't remove page {}", page_index);
+            return;
+        }
+
+        self.orders.remove(page_index);
+
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let start = page_index * rows_per_page;
+        self.tempo_lane.drain(start .. start + rows_per_page);
+
+        self.remove_unused_patterns();
+    }
+
+    /// Inserts a copy of the page at page_index right after it. The copy plays the same
+    /// patterns, unless unique is set, in which case each channel gets a copy of its pattern
+    pub fn duplicate_page(&mut self, page_index: usize, unique: bool){
+        let mut order = match self.orders.get(page_index) {
+            Some(order) => *order,
+            None => {
+                println!("Track::duplicate_page: page_index {} out of bounds", page_index);
+                return;
+            }
+        };
+
+        if unique {
+            for (channel, pattern) in order.iter_mut().enumerate() {
+                *pattern = self.new_pattern(channel, Some(*pattern));
+            }
+        }
+        self.orders.insert(page_index + 1, order);
+
+        let page = self.page_columns(page_index);
+        let tempo: Vec<Option<TempoEvent>> = self.tempo_lane[page.clone()].to_vec();
+        self.tempo_lane.splice(page.end .. page.end, tempo);
+    }
+
+    /// Moves the page at from so it becomes page to, shifting the pages between them over by one.
+    /// Every channel moves together, along with the page's tempo changes
+    pub fn move_page(&mut self, from: usize, to: usize){
+        if from >= self.orders.len() || to >= self.orders.len() {
+            println!("Track::move_page: Can't move page {} to {}", from, to);
+            return;
+        }
+
+        let order = self.orders.remove(from);
+        self.orders.insert(to, order);
+
+        let tempo: Vec<Option<TempoEvent>> = self.tempo_lane.drain(self.page_columns(from)).collect();
+        let start = self.page_columns(to).start;
+        self.tempo_lane.splice(start .. start, tempo);
+    }
+
+    /// Swaps two pages, along with their tempo changes
+    pub fn swap_pages(&mut self, first: usize, second: usize){
+        if first >= self.orders.len() || second >= self.orders.len() {
+            println!("Track::swap_pages: Can't swap page {} with {}", first, second);
+            return;
+        }
+        if first == second {
+            return;
+        }
+
+        self.orders.swap(first, second);
+
+        let (first, second) = (self.page_columns(first.min(second)), self.page_columns(first.max(second)));
+        let (head, tail) = self.tempo_lane.split_at_mut(second.start);
+        head[first].swap_with_slice(&mut tail[.. second.len()]);
+    }
+
+    /// The range of columns that make up a page
+    fn page_columns(&self, page_index: usize) -> std::ops::Range<usize>{
+        let rows_per_page = self.get_rows_per_page() as usize;
+        page_index * rows_per_page .. (page_index + 1) * rows_per_page
+    }
+
+    /// Inserts amount empty columns at column_index, pushing everything after them later.
+    /// channel None inserts into every channel and the tempo lane. Pages are added to the
+    /// end of the song if anything would be pushed off of it
+    pub fn insert_columns(&mut self, column_index: usize, amount: usize, channel: Option<usize>){
+        if column_index >= self.get_length() {
+            println!("Track::insert_columns: column_index {} out of bounds", column_index);
+            return;
+        }
+
+        let channels = self.edited_channels(channel);
+
+        // The column after the last one with anything in it, once it has been pushed back
+        let mut needed = channels.iter()
+            .filter_map(|channel| self.channel_columns(*channel).iter().rposition(|column| *column != WaveColumn::default()))
+            .max();
+        if channel.is_none() {
+            needed = needed.max(self.tempo_lane.iter().rposition(|event| event.is_some()));
+        }
+
+        if let Some(last) = needed.filter(|last| *last >= column_index) {
+            let needed_length = last + amount + 1;
+            let rows_per_page = self.get_rows_per_page() as usize;
+            self.add_pages(needed_length.saturating_sub(self.get_length()).div_ceil(rows_per_page));
+        }
+
+        let length = self.get_length();
+        for channel in channels {
+            let mut columns = self.channel_columns(channel);
+            columns.splice(column_index .. column_index, vec![WaveColumn::default(); amount]);
+            columns.truncate(length);
+
+            self.write_channel(channel, column_index, &columns[column_index ..]);
+        }
+
+        if channel.is_none() {
+            self.tempo_lane.splice(column_index .. column_index, vec![None; amount]);
+            self.tempo_lane.truncate(length);
+        }
+    }
+
+    /// Deletes amount columns at column_index, pulling everything after them forward.
+    /// channel None deletes from every channel and the tempo lane.
+    /// The end of the song is filled with empty columns
+    pub fn delete_columns(&mut self, column_index: usize, amount: usize, channel: Option<usize>){
+        let length = self.get_length();
+        if column_index >= length {
+            println!("Track::delete_columns: column_index {} out of bounds", column_index);
+            return;
+        }
+        let end = (column_index + amount).min(length);
+
+        for channel in self.edited_channels(channel) {
+            let mut columns = self.channel_columns(channel);
+            columns.drain(column_index .. end);
+            columns.resize(length, WaveColumn::default());
+
+            self.write_channel(channel, column_index, &columns[column_index ..]);
+        }
+
+        if channel.is_none() {
+            self.tempo_lane.drain(column_index .. end);
+            self.tempo_lane.resize(length, None);
+        }
+    }
+
+    /// The channels an edit is made to, where None is every channel
+    fn edited_channels(&self, channel: Option<usize>) -> Vec<usize>{
+        match channel {
+            Some(channel) => vec![channel],
+            None => (0 .. self.get_channel_count()).collect(),
+        }
+    }
+
+    /// Writes columns into a channel from column_index onwards. A page whose pattern
+    /// changes and is shared with another page is given its own copy first, so the
+    /// other pages aren't changed. Pages that end up the same keep their pattern
+    fn write_channel(&mut self, channel: usize, column_index: usize, columns: &[WaveColumn]){
+        let rows_per_page = self.get_rows_per_page() as usize;
+        let first_page = column_index / rows_per_page;
+
+        for page in first_page .. self.orders.len() {
+            let pattern = self.orders[page][channel];
+
+            let mut written = self.patterns[channel][pattern].clone();
+            for (row, target) in written.iter_mut().enumerate() {
+                let offset = (page * rows_per_page + row).checked_sub(column_index);
+                if let Some(column) = offset.and_then(|offset| columns.get(offset)) {
+                    *target = column.clone();
+                }
+            }
+
+            if written == self.patterns[channel][pattern] {
+                continue;
+            }
+
+            let shared = self.orders.iter().enumerate().any(|(i, order)| i != page && order[channel] == pattern);
+            if shared {
+                self.orders[page][channel] = self.new_pattern(channel, None);
+            }
+
+            let pattern = self.orders[page][channel];
+            self.patterns[channel][pattern] = written;
+        }
     }
 }
 
 
-/// The column of each note, its bools are represented as a binary number
-/// to keep from needing a large array of bools.
-#[derive(Clone, Serialize, Deserialize, Debug)]
+/// The note a channel plays on one column, along with its instrument, volume, and effect
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct WaveColumn {
-    column: u32,
+    /// The index of the note in the period table, which is also its row in the stepper.
+    /// None if the column has no pitch
+    note: Option<u8>,
+    event: NoteEvent,
+    /// The index of the instrument the note is played with.
+    /// None plays a plain 50% pulse at full volume
+    instrument: Option<u8>,
+    /// The channel volume from 0 to 15, set like a tracker's volume column.
+    /// None keeps the volume of the columns before it
+    volume: Option<u8>,
+    effect: Option<Effect>,
+}
+
+/// What a column does to the note playing on its channel
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum NoteEvent{
+    /// Nothing is played
+    Empty,
+    /// Starts a new note, restarting the oscillator
+    On,
+    /// Keeps the previous note playing without restarting it. Moves to the
+    /// new pitch if it is different, otherwise it is a tie
+    Hold,
+    /// Ends the previous note
+    Release,
 }
 
 impl Default for WaveColumn {
     fn default() -> Self {
         Self { 
-            column: 0,
+            note: None,
+            event: NoteEvent::Empty,
+            instrument: None,
+            volume: None,
+            effect: None,
         }
     }
 }
@@ -287,38 +1073,101 @@ impl Default for WaveColumn {
 
 impl WaveColumn{
 
-    /// Select the note pressed. ANDs the number to clear everything but the selected,
-    /// then XORs the number to create a toggle
+    /// Select the note pressed. Selecting the note that is already
+    /// selected toggles it off
     pub(crate) fn select(&mut self, index: u32){
-        let b = 1 << index;
+        if self.is_selected(index) {
+            self.note = None;
+            self.event = NoteEvent::Empty;
+        }
+        else {
+            self.note = Some(note_index(index));
+            self.event = NoteEvent::On;
+        }
+    }
+
+    /// Holds the previous note, moving it to the pitch at index
+    pub(crate) fn hold(&mut self, index: u32){
+        self.note = Some(note_index(index));
+        self.event = NoteEvent::Hold;
+    }
 
-        self.column &= b;
-        self.column ^= b;
+    /// Moves the note to the pitch at index, keeping its event
+    pub(crate) fn set_row(&mut self, index: u32){
+        if self.note.is_some() {
+            self.note = Some(note_index(index));
+        }
+    }
+
+    /// Releases the previous note
+    pub(crate) fn release(&mut self){
+        self.note = None;
+        self.event = NoteEvent::Release;
     }
 
     /// Checks if the current note is selected
     pub(crate) fn is_selected(&self, index: u32) -> bool{
-        self.column == 1 << index
+        self.note.map(u32::from) == Some(index)
+    }
+
+    pub(crate) fn is_held(&self) -> bool{
+        self.event == NoteEvent::Hold
+    }
+
+    pub(crate) fn is_released(&self) -> bool{
+        self.event == NoteEvent::Release
+    }
+
+    pub(crate) fn get_event(&self) -> NoteEvent{
+        self.event
+    }
+
+    pub(crate) fn get_instrument(&self) -> Option<u8>{
+        self.instrument
+    }
+
+    pub(crate) fn set_instrument(&mut self, instrument: Option<u8>){
+        self.instrument = instrument;
+    }
+
+    pub(crate) fn get_volume(&self) -> Option<u8>{
+        self.volume
+    }
+
+    pub(crate) fn set_volume(&mut self, volume: Option<u8>){
+        self.volume = volume.map(|volume| volume.min(MAX_VOLUME));
+    }
+
+    pub(crate) fn get_effect(&self) -> Option<Effect>{
+        self.effect
+    }
+
+    pub(crate) fn set_effect(&mut self, effect: Option<Effect>){
+        self.effect = effect;
     }
 
     pub(crate) fn remove(&mut self){
-        self.column &= 0;
+        self.note = None;
+        self.event = NoteEvent::Empty;
+    }
+
+    /// The row of the note in the stepper, or None if the column has no pitch
+    pub(crate) fn get_row(&self) -> Option<u32>{
+        self.note.map(u32::from)
     }
 
-    /// Returns the index of the current columns note
+    /// Returns the index of the current columns note in the period table
     /// If non selected, return -1
     pub(crate) fn get_index(&self) -> i32{
-        let index = self.column.trailing_zeros();
-
-        if index == 32 {
-            return -1;
-        }
-
-        // + 36 so that the base note frequency is 440 rather than 24
-        index as i32 + 36
+        self.note.map_or(-1, i32::from)
     }
 }
 
+/// A row of the stepper as a note, kept inside the period table
+fn note_index(row: u32) -> u8{
+    row.min(waves::NOTE_COUNT as u32 - 1) as u8
+}
+
 
 ///
 /// Temporarily moved here
@@ -381,4 +1230,329 @@ pub(crate) fn play_nes_noise(){
         sink.append(source);
         sink.sleep_until_end();
     });
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn note(row: u32) -> WaveColumn{
+        let mut column = WaveColumn::default();
+        column.select(row);
+        column
+    }
+
+    fn set_note(track: &mut Track, column_index: usize, channel: usize, row: u32){
+        *track.get_column_mut(column_index, channel).unwrap() = note(row);
+    }
+
+    fn row_at(track: &Track, column_index: usize, channel: usize) -> Option<u32>{
+        track.get_column(column_index, channel).unwrap().get_row()
+    }
+
+    #[test]
+    fn insert_columns_moves_notes_and_tempo(){
+        let mut track = Track::new(2);
+        set_note(&mut track, 5, 0, 40);
+        set_note(&mut track, 5, 3, 2);
+        track.tempo_lane[5] = Some(TempoEvent::Bpm(120));
+
+        track.insert_columns(3, 2, None);
+
+        assert_eq!(row_at(&track, 5, 0), None);
+        assert_eq!(row_at(&track, 7, 0), Some(40));
+        assert_eq!(row_at(&track, 7, 3), Some(2));
+        assert_eq!(track.tempo_lane[5], None);
+        assert_eq!(track.tempo_lane[7], Some(TempoEvent::Bpm(120)));
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+    }
+
+    #[test]
+    fn insert_columns_adds_pages_instead_of_losing_notes(){
+        let mut track = Track::new(2);
+        let last = track.get_length() - 1;
+        set_note(&mut track, last, 1, 12);
+        track.tempo_lane[last] = Some(TempoEvent::Speed(3));
+
+        track.insert_columns(0, 1, None);
+
+        assert_eq!(track.get_page_count(), 3);
+        assert_eq!(row_at(&track, last + 1, 1), Some(12));
+        assert_eq!(track.tempo_lane[last + 1], Some(TempoEvent::Speed(3)));
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+    }
+
+    #[test]
+    fn delete_columns_pulls_notes_and_tempo_forward(){
+        let mut track = Track::new(2);
+        set_note(&mut track, 10, 2, 30);
+        track.tempo_lane[10] = Some(TempoEvent::Bpm(90));
+        let length = track.get_length();
+
+        track.delete_columns(4, 3, None);
+
+        assert_eq!(row_at(&track, 7, 2), Some(30));
+        assert_eq!(row_at(&track, 10, 2), None);
+        assert_eq!(track.tempo_lane[7], Some(TempoEvent::Bpm(90)));
+        assert_eq!(track.get_length(), length);
+        assert_eq!(track.tempo_lane.len(), length);
+    }
+
+    #[test]
+    fn channel_edits_leave_the_tempo_lane_alone(){
+        let mut track = Track::new(2);
+        track.tempo_lane[10] = Some(TempoEvent::Bpm(90));
+
+        track.insert_columns(4, 3, Some(0));
+        track.delete_columns(0, 1, Some(1));
+
+        assert_eq!(track.tempo_lane[10], Some(TempoEvent::Bpm(90)));
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+    }
+
+    #[test]
+    fn smaller_pages_keep_every_note(){
+        let mut track = Track::new(4);
+        let last = track.get_length() - 1;
+        set_note(&mut track, last, 0, 12);
+        set_note(&mut track, 40, 2, 30);
+        track.tempo_lane[last] = Some(TempoEvent::Bpm(90));
+
+        track.set_page_layout(3, 4, 2);
+        assert_eq!(track.get_rows_per_page(), 24);
+        assert_eq!(track.get_page_count(), 6);
+        assert_eq!(row_at(&track, last, 0), Some(12));
+        assert_eq!(row_at(&track, 40, 2), Some(30));
+        assert_eq!(track.tempo_lane[last], Some(TempoEvent::Bpm(90)));
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+
+        track.set_page_layout(4, 4, 2);
+        assert_eq!(track.get_page_count(), 4);
+        assert_eq!(row_at(&track, last, 0), Some(12));
+        assert_eq!(row_at(&track, 40, 2), Some(30));
+        assert_eq!(track.tempo_lane[last], Some(TempoEvent::Bpm(90)));
+    }
+
+    #[test]
+    fn changing_the_layout_and_back_keeps_the_length(){
+        let mut track = Track::new(4);
+        set_note(&mut track, 3, 1, 20);
+
+        for rows_per_beat in [3, 2, 1, 2, 3, 4] {
+            track.set_page_layout(rows_per_beat, 4, 2);
+        }
+        assert_eq!(track.get_page_count(), 4);
+        assert_eq!(row_at(&track, 3, 1), Some(20));
+
+        track.set_page_layout(4, 3, 2);
+        track.set_page_layout(4, 4, 2);
+        assert_eq!(track.get_page_count(), 4);
+    }
+
+    #[test]
+    fn the_same_page_size_keeps_the_patterns(){
+        let mut track = Track::new(2);
+        track.set_order(1, 0, track.orders[0][0]);
+
+        track.set_page_layout(4, 2, 4);
+        assert_eq!(track.get_rows_per_page(), 32);
+        assert_eq!(track.orders[1][0], track.orders[0][0]);
+        assert_eq!((track.beats_per_measure, track.measures_per_page), (2, 4));
+    }
+
+    #[test]
+    fn shared_patterns_are_only_copied_when_they_change(){
+        let mut track = Track::new(2);
+        let rows_per_page = track.get_rows_per_page() as usize;
+        track.set_order(1, 0, track.orders[0][0]);
+        set_note(&mut track, 2, 0, 20);
+        let pattern_count = track.patterns[0].len();
+
+        // Everything from here to the end of the page is empty, so nothing changes
+        track.insert_columns(rows_per_page + 8, 1, Some(0));
+        assert_eq!(track.orders[1][0], track.orders[0][0]);
+        assert_eq!(track.patterns[0].len(), pattern_count);
+
+        // The shared pattern plays this note on both pages
+        set_note(&mut track, rows_per_page + 13, 0, 25);
+        assert_eq!(row_at(&track, 13, 0), Some(25));
+
+        track.insert_columns(rows_per_page + 8, 1, Some(0));
+        assert_ne!(track.orders[1][0], track.orders[0][0]);
+
+        // The first page is untouched, and the second kept the notes before the insert
+        assert_eq!(row_at(&track, 2, 0), Some(20));
+        assert_eq!(row_at(&track, 13, 0), Some(25));
+        assert_eq!(row_at(&track, rows_per_page + 2, 0), Some(20));
+        assert_eq!(row_at(&track, rows_per_page + 13, 0), None);
+        assert_eq!(row_at(&track, rows_per_page + 14, 0), Some(25));
+    }
+
+    /// A track of four pages, each with its own patterns, and a tempo change on the first
+    /// column of each page that gives its page number
+    fn numbered_pages() -> Track{
+        let mut track = Track::new(4);
+        let rows_per_page = track.get_rows_per_page() as usize;
+        for page in 0 .. 4 {
+            track.tempo_lane[page * rows_per_page] = Some(TempoEvent::Bpm(page as u32));
+        }
+        track
+    }
+
+    /// The page numbers of numbered_pages, in the order they are now played
+    fn page_numbers(track: &Track) -> Vec<u32>{
+        let rows_per_page = track.get_rows_per_page() as usize;
+        track.tempo_lane.chunks(rows_per_page)
+            .map(|page| match page[0] {
+                Some(TempoEvent::Bpm(page)) => page,
+                _ => panic!("A page lost its tempo change"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn move_page_shifts_the_pages_between(){
+        let mut track = numbered_pages();
+        let orders = track.orders.clone();
+
+        track.move_page(0, 2);
+        assert_eq!(track.orders, vec![orders[1], orders[2], orders[0], orders[3]]);
+        assert_eq!(page_numbers(&track), vec![1, 2, 0, 3]);
+
+        track.move_page(3, 0);
+        assert_eq!(track.orders, vec![orders[3], orders[1], orders[2], orders[0]]);
+        assert_eq!(page_numbers(&track), vec![3, 1, 2, 0]);
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+    }
+
+    #[test]
+    fn swap_pages_only_moves_two_pages(){
+        let mut track = numbered_pages();
+        let orders = track.orders.clone();
+
+        track.swap_pages(3, 1);
+        assert_eq!(track.orders, vec![orders[0], orders[3], orders[2], orders[1]]);
+        assert_eq!(page_numbers(&track), vec![0, 3, 2, 1]);
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+    }
+
+    #[test]
+    fn duplicate_page_copies_the_order_and_tempo(){
+        let mut track = numbered_pages();
+        let orders = track.orders.clone();
+
+        track.duplicate_page(1, false);
+        assert_eq!(track.orders, vec![orders[0], orders[1], orders[1], orders[2], orders[3]]);
+        assert_eq!(page_numbers(&track), vec![0, 1, 1, 2, 3]);
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+
+        // A unique copy gets new patterns holding the same notes
+        set_note(&mut track, 0, 0, 9);
+        track.duplicate_page(0, true);
+        let rows_per_page = track.get_rows_per_page() as usize;
+
+        assert!(track.orders[1].iter().zip(&orders[0]).all(|(copy, original)| copy != original));
+        assert_eq!(row_at(&track, rows_per_page, 0), Some(9));
+        assert_eq!(page_numbers(&track), vec![0, 0, 1, 1, 2, 3]);
+    }
+
+    /// A track in the layout saved before .rsf files were versioned, as bincode wrote it
+    fn legacy_file(channels: [Vec<u32>; 4], page_count: u32) -> Vec<u8>{
+        let mut bytes = Vec::new();
+        for channel in channels {
+            bytes.extend((channel.len() as u64).to_le_bytes());
+            for column in channel {
+                bytes.extend(column.to_le_bytes());
+            }
+        }
+        bytes.extend(page_count.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn legacy_tracks_are_migrated(){
+        let mut pulse = vec![0; 64];
+        pulse[0] = 1;
+        pulse[2] = 1 << 31;
+        let mut triangle = vec![0; 64];
+        triangle[40] = 1 << 3;
+
+        let path = std::env::temp_dir().join("rustnes_legacy_track.rsf");
+        std::fs::write(&path, legacy_file([pulse, Vec::new(), triangle, Vec::new()], 2)).unwrap();
+
+        let mut synth = Synth::new();
+        let loaded = synth.load_project(path.clone());
+        std::fs::remove_file(&path).unwrap();
+        loaded.unwrap();
+
+        // The bottom row of the old stepper is moved up to the same note
+        let track = synth.track();
+        assert_eq!(synth.project.songs.len(), 1);
+        assert_eq!(track.get_page_count(), 2);
+        assert_eq!(row_at(track, 0, 0), Some(LEGACY_NOTE_OFFSET));
+        assert_eq!(track.get_column(0, 0).unwrap().get_event(), NoteEvent::On);
+        assert_eq!(row_at(track, 1, 0), None);
+        assert_eq!(track.get_column(1, 0).unwrap().get_event(), NoteEvent::Empty);
+        assert_eq!(row_at(track, 2, 0), Some(31 + LEGACY_NOTE_OFFSET));
+        assert_eq!(row_at(track, 40, 2), Some(3 + LEGACY_NOTE_OFFSET));
+        assert_eq!(row_at(track, 40, 1), None);
+
+        // Legacy tracks played 960 columns a minute
+        assert_eq!(timing::get_rows_per_second(track.bpm, track.speed, track.rows_per_beat), 16.0);
+        assert_eq!(track.get_rows_per_page(), 32);
+        assert!(track.tempo_lane.iter().all(|event| event.is_none()));
+        assert_eq!(track.tempo_lane.len(), track.get_length());
+
+        // Saving the migrated project and opening it again changes nothing
+        let path = std::env::temp_dir().join("rustnes_migrated_track.rsf");
+        synth.save_project(path.clone()).unwrap();
+        let migrated = synth.project.clone();
+        let reloaded = synth.load_project(path.clone());
+        std::fs::remove_file(&path).unwrap();
+        reloaded.unwrap();
+
+        assert_eq!(synth.project, migrated);
+    }
+
+    #[test]
+    fn removing_a_groove_clears_the_events_using_it(){
+        let mut track = Track::new(1);
+        track.grooves = vec![Groove::default(), Groove::default(), Groove::default()];
+        track.groove = Some(2);
+        track.tempo_lane[1] = Some(TempoEvent::Groove(0));
+        track.tempo_lane[2] = Some(TempoEvent::Groove(1));
+        track.tempo_lane[3] = Some(TempoEvent::Groove(2));
+        track.tempo_lane[4] = Some(TempoEvent::Bpm(100));
+
+        track.remove_groove(1);
+
+        assert_eq!(track.grooves.len(), 2);
+        assert_eq!(track.groove, Some(1));
+        assert_eq!(track.tempo_lane[1], Some(TempoEvent::Groove(0)));
+        assert_eq!(track.tempo_lane[2], None);
+        assert_eq!(track.tempo_lane[3], Some(TempoEvent::Groove(1)));
+        assert_eq!(track.tempo_lane[4], Some(TempoEvent::Bpm(100)));
+
+        // The columns after the removed groove's event keep using the groove before it
+        assert_eq!(track.get_groove_at(2), Some(0));
+
+        track.remove_groove(1);
+        assert_eq!(track.groove, None);
+        assert_eq!(track.get_groove_at(0), None);
+        assert_eq!(track.get_groove_at(3), Some(0));
+    }
+
+    #[test]
+    fn groove_at_follows_groove_and_speed_events(){
+        let mut track = Track::new(1);
+        track.grooves = vec![Groove::default(), Groove::default()];
+        track.groove = Some(1);
+        track.tempo_lane[4] = Some(TempoEvent::Groove(0));
+        track.tempo_lane[8] = Some(TempoEvent::Speed(3));
+
+        assert_eq!(track.get_groove_at(0), Some(1));
+        assert_eq!(track.get_groove_at(4), Some(0));
+        assert_eq!(track.get_groove_at(7), Some(0));
+        assert_eq!(track.get_groove_at(8), None);
+        assert_eq!(track.get_speed_at(8), 3);
+    }
+}
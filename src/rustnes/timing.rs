@@ -0,0 +1,348 @@
+use serde::{Serialize, Deserialize};
+
+use super::synth::{Track, TempoEvent, Groove};
+
+// The sample rate every oscillator runs at
+pub const SAMPLE_RATE: f32 = 48000.0;
+
+// The speed that a tempo in bpm is written for. At this speed a row lasts
+// exactly 1 / rows_per_beat of a beat, the same as FamiTracker's default of 6
+pub const DEFAULT_SPEED: u32 = 6;
+
+/// How the length of a column is decided
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum TimingMode{
+    /// Columns last exactly as long as the tempo asks for
+    RealTime,
+    /// Columns last a whole number of engine frames, like an NES sound driver
+    EngineTick,
+}
+
+/// The region of the NES, which decides how often the sound driver updates
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Region{
+    Ntsc,
+    Pal,
+}
+
+impl Region{
+    pub fn frames_per_second(&self) -> u32{
+        match self {
+            Region::Ntsc => 60,
+            Region::Pal => 50,
+        }
+    }
+
+    pub fn samples_per_frame(&self) -> f32{
+        SAMPLE_RATE / self.frames_per_second() as f32
+    }
+}
+
+/// Converts a tempo into how many columns are played each second
+pub fn get_rows_per_second(bpm: u32, speed: u32, rows_per_beat: u32) -> f32{
+    (bpm * rows_per_beat * DEFAULT_SPEED) as f32 / (60 * speed) as f32
+}
+
+///
+/// Decides when the oscillators move on to the next column.
+/// Follows the tempo lane of the track, and in EngineTick mode uses the same
+/// tempo accumulator as FamiTracker so that columns are whole frames long.
+/// While a groove is in use its frame counts are used instead of the tempo
+///
+#[derive(Clone, Debug)]
+pub struct RowClock{
+    timing_mode: TimingMode,
+    region: Region,
+
+    bpm: u32,
+    speed: u32,
+    rows_per_beat: u32,
+    tempo_lane: Vec<Option<TempoEvent>>,
+
+    grooves: Vec<Groove>,
+    groove: Option<usize>,
+    groove_position: usize,
+
+    // Counts down every frame, and a new row starts once it reaches 0
+    tempo_accumulator: i64,
+
+    row_index: usize,
+    row_position: f32,
+    row_length: f32,
+}
+
+impl RowClock{
+    pub fn new(track: &Track) -> RowClock{
+        let mut clock = RowClock {
+            timing_mode: track.timing_mode,
+            region: track.region,
+
+            bpm: track.bpm,
+            speed: track.speed,
+            rows_per_beat: track.rows_per_beat,
+            tempo_lane: track.tempo_lane.to_vec(),
+
+            grooves: track.grooves.to_vec(),
+            groove: track.groove,
+            groove_position: 0,
+
+            tempo_accumulator: 0,
+
+            row_index: 0,
+            row_position: 0.0,
+            row_length: 0.0,
+        };
+
+        clock.row_length = clock.start_row();
+        clock
+    }
+
+    /// The column currently being played
+    pub fn row_index(&self) -> usize{
+        self.row_index
+    }
+
+    /// Moves forward one sample, and onto the next column once the
+    /// current one has played for its whole length
+    pub fn tick(&mut self){
+        self.row_position += 1.0;
+
+        if self.row_position >= self.row_length {
+            self.row_position -= self.row_length;
+            self.row_index += 1;
+            self.row_length = self.start_row();
+        }
+    }
+
+    /// The length in seconds of every column from the current one onwards
+    pub fn get_duration(mut self) -> f32{
+        let mut samples = 0.0;
+
+        while self.row_index < self.tempo_lane.len() {
+            samples += self.row_length;
+            self.row_index += 1;
+            self.row_length = self.start_row();
+        }
+
+        samples / SAMPLE_RATE
+    }
+
+    /// Applies the tempo event of the current column, then returns how
+    /// many samples the column lasts
+    fn start_row(&mut self) -> f32{
+        match self.tempo_lane.get(self.row_index) {
+            Some(Some(TempoEvent::Bpm(bpm))) => self.bpm = *bpm,
+            Some(Some(TempoEvent::Speed(speed))) => {
+                self.speed = *speed;
+                self.groove = None;
+            },
+            Some(Some(TempoEvent::Groove(groove))) => {
+                self.groove = Some(*groove);
+                self.groove_position = 0;
+            },
+            _ => (),
+        }
+
+        if let Some(frames) = self.next_groove_frames() {
+            return frames as f32 * self.region.samples_per_frame();
+        }
+
+        match self.timing_mode {
+            TimingMode::RealTime => SAMPLE_RATE / get_rows_per_second(self.bpm, self.speed, self.rows_per_beat),
+            TimingMode::EngineTick => self.count_row_frames() as f32 * self.region.samples_per_frame(),
+        }
+    }
+
+    /// The length of the next column from the current groove.
+    /// Returns None when no groove is used, or the groove is empty
+    fn next_groove_frames(&mut self) -> Option<u32>{
+        let frames = &self.grooves.get(self.groove?)?.frames;
+        if frames.is_empty() {
+            return None;
+        }
+
+        let length = frames[self.groove_position % frames.len()].max(1);
+        self.groove_position += 1;
+        Some(length)
+    }
+
+    /// Runs the tempo accumulator until the next row, counting the frames.
+    /// The remainder is carried over, so uneven tempos alternate between lengths
+    fn count_row_frames(&mut self) -> u32{
+        let decrement = ((self.bpm * self.rows_per_beat * DEFAULT_SPEED) / self.speed).max(1) as i64;
+        self.tempo_accumulator += (60 * self.region.frames_per_second()) as i64;
+
+        let mut frames = 0;
+        while self.tempo_accumulator > 0 {
+            self.tempo_accumulator -= decrement;
+            frames += 1;
+        }
+
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    /// How many samples each of the first rows of a track lasts
+    fn row_lengths(track: &Track, rows: usize) -> Vec<u32>{
+        let mut clock = RowClock::new(track);
+        let mut lengths = vec![0; rows];
+
+        while clock.row_index() < rows {
+            lengths[clock.row_index()] += 1;
+            clock.tick();
+        }
+        lengths
+    }
+
+    #[test]
+    fn rows_per_second(){
+        assert_eq!(get_rows_per_second(240, DEFAULT_SPEED, 4), 16.0);
+        assert_eq!(get_rows_per_second(120, DEFAULT_SPEED, 4), 8.0);
+        assert_eq!(get_rows_per_second(150, DEFAULT_SPEED, 2), 5.0);
+
+        // Halving the speed plays rows twice as fast
+        assert_eq!(get_rows_per_second(240, 3, 4), 32.0);
+        assert_eq!(get_rows_per_second(240, 12, 4), 8.0);
+    }
+
+    #[test]
+    fn tempo_changes_start_on_their_row(){
+        let mut track = Track::new(1);
+        track.tempo_lane[2] = Some(TempoEvent::Bpm(120));
+        track.tempo_lane[4] = Some(TempoEvent::Speed(3));
+
+        // 16 rows a second at first, then 8, then 16 again at the faster speed
+        assert_eq!(row_lengths(&track, 6), vec![3000, 3000, 6000, 6000, 3000, 3000]);
+    }
+
+    #[test]
+    fn tempo_change_on_the_first_row(){
+        let mut track = Track::new(1);
+        track.tempo_lane[0] = Some(TempoEvent::Bpm(480));
+
+        assert_eq!(row_lengths(&track, 2), vec![1500, 1500]);
+    }
+
+    #[test]
+    fn real_time_rows_ignore_the_region(){
+        let mut track = Track::new(1);
+        let ntsc = row_lengths(&track, 8);
+
+        track.region = Region::Pal;
+        assert_eq!(row_lengths(&track, 8), ntsc);
+    }
+
+    #[test]
+    fn regions_update_at_different_rates(){
+        assert_eq!(Region::Ntsc.frames_per_second(), 60);
+        assert_eq!(Region::Pal.frames_per_second(), 50);
+        assert_eq!(Region::Ntsc.samples_per_frame(), 800.0);
+        assert_eq!(Region::Pal.samples_per_frame(), 960.0);
+    }
+
+    #[test]
+    fn duration_follows_the_tempo_lane(){
+        let mut track = Track::new(1);
+        assert_eq!(track.get_duration(), 2.0);
+
+        // The second half of the page plays at half the tempo
+        track.tempo_lane[16] = Some(TempoEvent::Bpm(120));
+        assert_eq!(track.get_duration(), 3.0);
+    }
+
+    /// How many engine frames each of the first rows of a track lasts
+    fn row_frames(track: &Track, rows: usize) -> Vec<u32>{
+        let samples_per_frame = track.region.samples_per_frame() as u32;
+        row_lengths(track, rows).into_iter().map(|length| {
+            assert_eq!(length % samples_per_frame, 0, "Rows should be whole frames long");
+            length / samples_per_frame
+        }).collect()
+    }
+
+    fn engine_tick_track(region: Region) -> Track{
+        let mut track = Track::new(1);
+        track.timing_mode = TimingMode::EngineTick;
+        track.region = region;
+        track
+    }
+
+    #[test]
+    fn engine_tick_carries_the_remainder(){
+        // 240 BPM at 4 rows a beat is 3.75 NTSC frames a row
+        let track = engine_tick_track(Region::Ntsc);
+        assert_eq!(row_frames(&track, 8), vec![4, 4, 4, 3, 4, 4, 4, 3]);
+    }
+
+    #[test]
+    fn engine_tick_in_pal(){
+        // The same tempo is 3.125 PAL frames a row, so the rows last as long in seconds
+        let track = engine_tick_track(Region::Pal);
+        let frames = row_frames(&track, 8);
+
+        assert_eq!(frames, vec![4, 3, 3, 3, 3, 3, 3, 3]);
+
+        // Eight rows take half a second, the same as the 30 NTSC frames they take
+        assert_eq!(frames.iter().sum::<u32>(), 25);
+    }
+
+    #[test]
+    fn engine_tick_even_tempo(){
+        // FamiTracker's default of 150 BPM at speed 6 is exactly 6 frames a row
+        let mut track = engine_tick_track(Region::Ntsc);
+        track.bpm = 150;
+        assert_eq!(row_frames(&track, 4), vec![6, 6, 6, 6]);
+
+        track.speed = 3;
+        assert_eq!(row_frames(&track, 4), vec![3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn engine_tick_tempo_change_keeps_the_remainder(){
+        let mut track = engine_tick_track(Region::Ntsc);
+        track.bpm = 150;
+        track.tempo_lane[2] = Some(TempoEvent::Bpm(240));
+
+        assert_eq!(row_frames(&track, 6), vec![6, 6, 4, 4, 4, 3]);
+    }
+
+    fn groove(frames: Vec<u32>) -> Groove{
+        Groove { name: String::new(), frames }
+    }
+
+    #[test]
+    fn grooves_cycle_across_rows(){
+        let mut track = Track::new(1);
+        track.grooves = vec![groove(vec![8, 4, 6])];
+        track.groove = Some(0);
+
+        assert_eq!(row_frames(&track, 7), vec![8, 4, 6, 8, 4, 6, 8]);
+    }
+
+    #[test]
+    fn groove_events_restart_and_speed_events_stop_grooves(){
+        let mut track = Track::new(1);
+        track.grooves = vec![groove(vec![8, 4]), groove(vec![2, 3, 5])];
+        track.groove = Some(0);
+        track.tempo_lane[3] = Some(TempoEvent::Groove(1));
+        track.tempo_lane[7] = Some(TempoEvent::Groove(0));
+        track.tempo_lane[9] = Some(TempoEvent::Speed(3));
+
+        // The speed event turns the groove off, and speed 3 at 240 BPM plays 32 rows a second
+        assert_eq!(row_lengths(&track, 11), vec![6400, 3200, 6400, 1600, 2400, 4000, 1600, 6400, 3200, 1500, 1500]);
+    }
+
+    #[test]
+    fn missing_and_empty_grooves_use_the_tempo(){
+        let mut track = Track::new(1);
+        track.groove = Some(2);
+        assert_eq!(row_lengths(&track, 2), vec![3000, 3000]);
+
+        track.grooves = vec![groove(Vec::new())];
+        track.groove = Some(0);
+        assert_eq!(row_lengths(&track, 2), vec![3000, 3000]);
+    }
+}
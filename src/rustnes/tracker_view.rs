@@ -0,0 +1,194 @@
+use egui::{RichText, Sense, Key, Modifiers};
+
+use super::RustNES;
+use super::synth::{WaveColumn, NoteEvent, MAX_VOLUME};
+use super::{waves, instrument_name};
+
+// The height of the tracker view, about the same as the note stepper and volume lane
+const TRACKER_HEIGHT: f32 = 400.0;
+
+/// The editor shown in the middle of the window. Both edit the same song
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EditorView{
+    /// The note stepper and volume lane
+    PianoRoll,
+    /// Every channel side by side as rows of text, like FamiTracker
+    Tracker,
+}
+
+impl RustNES{
+    ///
+    /// The page as rows of text, with a cell for each channel holding its note, instrument,
+    /// volume, and effect. Clicking a cell moves the scrubber and selects the channel,
+    /// and right clicking it edits the cell. The arrow keys move between cells
+    ///
+    pub(crate) fn tracker_view(&mut self, ui: &mut egui::Ui){
+        let moved = self.tracker_keys(ui.ctx());
+
+        let rows_per_page = self.synth.get_notes_per_page() as usize;
+        let rows_per_beat = self.synth.track().rows_per_beat as usize;
+        let first_column = self.selected_page * rows_per_page;
+
+        egui::ScrollArea::vertical()
+        .max_height(TRACKER_HEIGHT)
+        .auto_shrink([false, false])
+        .show(ui, |ui| {
+            egui::Grid::new("tracker_grid").spacing(egui::vec2(12.0, 0.0)).show(ui, |ui| {
+                ui.label("");
+                for channel in 0 .. self.synth.track().get_channel_count() {
+                    ui.monospace(&self.channel_symbol[channel]);
+                }
+                ui.end_row();
+
+                for row in 0 .. rows_per_page {
+                    let column_index = first_column + row;
+
+                    let row_number = RichText::new(format!("{:02X}", row)).monospace();
+                    ui.label(if row % rows_per_beat == 0 {row_number.color(self.selected_color)} else {row_number});
+
+                    for channel in 0 .. self.synth.track().get_channel_count() {
+                        let response = self.tracker_cell(ui, column_index, channel);
+
+                        if moved && column_index == self.scrubber_start && channel == self.selected_channel {
+                            response.scroll_to_me(None);
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+    }
+
+    /// One channel of one row in the tracker view
+    fn tracker_cell(&mut self, ui: &mut egui::Ui, column_index: usize, channel: usize) -> egui::Response{
+        let column = match self.synth.track().get_column(column_index, channel) {
+            Some(column) => column.clone(),
+            None => return ui.label(""),
+        };
+
+        let in_selection = matches!(self.selection, Some(selection) if
+            (selection.first_column ..= selection.last_column).contains(&column_index)
+            && selection.channel.is_none_or(|selected| selected == channel)
+            && selection.includes(&column));
+
+        let mut text = RichText::new(cell_text(&column)).monospace();
+        text = match column.get_event() {
+            NoteEvent::Hold => text.color(self.hold_color),
+            NoteEvent::Release => text.color(self.release_color),
+            _ => text,
+        };
+        if column_index == self.scrubber_start {
+            text = text.background_color(if channel == self.selected_channel {self.scrubber_color} else {self.highlight_color});
+        }
+        else if in_selection {
+            text = text.background_color(self.selection_color);
+        }
+
+        let response = ui.add(egui::Label::new(text).sense(Sense::click()));
+
+        if response.clicked() || response.secondary_clicked() {
+            self.scrubber_start = column_index;
+            self.selected_channel = channel;
+        }
+
+        response.context_menu(|ui| self.tracker_cell_menu(ui, column_index))
+    }
+
+    /// The right click menu of a tracker cell, for the instrument, volume, and effect of the selected channel
+    fn tracker_cell_menu(&mut self, ui: &mut egui::Ui, column_index: usize){
+        let (mut instrument, mut volume) = match self.synth.track().get_column(column_index, self.selected_channel) {
+            Some(column) => (column.get_instrument(), column.get_volume()),
+            None => return,
+        };
+
+        let instruments = &self.synth.project.instruments;
+        ui.horizontal(|ui| {
+            ui.label("Instrument");
+            egui::ComboBox::from_id_source("tracker_instrument")
+                .selected_text(instrument.map_or("None".to_owned(), |i| instrument_name(instruments, i as usize)))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut instrument, None, "None");
+                    for i in 0 .. instruments.len() {
+                        ui.selectable_value(&mut instrument, Some(i as u8), instrument_name(instruments, i));
+                    }
+                });
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Volume");
+            match &mut volume {
+                Some(value) => {
+                    ui.add(egui::DragValue::new(value).clamp_range(0..=MAX_VOLUME).hexadecimal(1, false, true));
+                    if ui.button("Clear").clicked() {
+                        volume = None;
+                    }
+                },
+                None => {
+                    if ui.button("Set").clicked() {
+                        volume = Some(MAX_VOLUME);
+                    }
+                },
+            }
+        });
+
+        if let Some(column) = self.synth.get_channel_column(column_index, self.selected_channel) {
+            column.set_instrument(instrument);
+            column.set_volume(volume);
+        }
+
+        ui.separator();
+        self.effect_menu(ui, column_index);
+    }
+
+    /// Moves the scrubber with the up and down arrows, and the selected channel with left and right.
+    /// Left and right are left for step entry while it is on. Returns true if the scrubber or channel moved
+    fn tracker_keys(&mut self, ctx: &egui::Context) -> bool{
+        if ctx.memory().focus().is_some() {
+            return false;
+        }
+
+        let mut input = ctx.input_mut();
+        let up = input.consume_key(Modifiers::NONE, Key::ArrowUp);
+        let down = input.consume_key(Modifiers::NONE, Key::ArrowDown);
+        // During step entry the left and right arrows move the scrubber, like in the note stepper
+        let left = !self.step_entry && input.consume_key(Modifiers::NONE, Key::ArrowLeft);
+        let right = !self.step_entry && input.consume_key(Modifiers::NONE, Key::ArrowRight);
+        drop(input);
+
+        if up {
+            self.move_scrubber(-1);
+        }
+        if down {
+            self.move_scrubber(1);
+        }
+
+        let last_channel = self.synth.track().get_channel_count() - 1;
+        if left {
+            self.selected_channel = self.selected_channel.saturating_sub(1);
+        }
+        if right {
+            self.selected_channel = (self.selected_channel + 1).min(last_channel);
+        }
+
+        up || down || left || right
+    }
+}
+
+/// A cell of the tracker view, written like FamiTracker: `C#4 00 F 4A3`.
+/// Releases are written as `===`, and anything missing as dots
+fn cell_text(column: &WaveColumn) -> String{
+    let note = match (column.get_event(), column.get_row()) {
+        (NoteEvent::Release, _) => "===".to_owned(),
+        (_, Some(row)) => {
+            let name = waves::note_name(row);
+            // Natural notes are padded with a dash so every note is 3 characters
+            if name.contains('#') {name} else {format!("{}-{}", &name[..1], &name[1..])}
+        },
+        _ => "...".to_owned(),
+    };
+    let instrument = column.get_instrument().map_or("..".to_owned(), |instrument| format!("{:02X}", instrument));
+    let volume = column.get_volume().map_or(".".to_owned(), |volume| format!("{:X}", volume));
+    let effect = column.get_effect().map_or("...".to_owned(), |effect| effect.to_string());
+
+    format!("{} {} {} {}", note, instrument, volume, effect)
+}
@@ -1,11 +1,18 @@
 use std::{time::Duration};
 use rand::Rng;
 
+// Mute and solo are shared with the play thread, so they can change while a song plays
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use crate::Source;
 
 // TODO make this not use a super
-use super::synth::{WaveColumn, Track};
+use super::synth::{WaveColumn, Track, NoteEvent};
 use crate::rustnes::filters;
+use crate::rustnes::timing::RowClock;
+use crate::rustnes::instrument::{Instrument, InstrumentPlayer, SequenceKind};
+use crate::rustnes::effect::{Effect, EffectCommand, EffectState};
 
 // The period table of the NES
 const PERIODS: &'static [u32] = &[
@@ -19,6 +26,71 @@ const PERIODS: &'static [u32] = &[
     35,  33,  31,  29,  27,  26,  24,  23,  21,  20,
 ];
 
+/// How many notes the period table has
+pub const NOTE_COUNT: usize = PERIODS.len();
+
+// The MIDI note number of the first note of the period table, A1 on the pulse channels
+const FIRST_MIDI_NOTE: u32 = 33;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// The name of a note of the period table, like "C4" or "D#4"
+pub fn note_name(note: u32) -> String{
+    let midi_note = note + FIRST_MIDI_NOTE;
+    format!("{}{}", NOTE_NAMES[(midi_note % 12) as usize], midi_note / 12 - 1)
+}
+
+/// If a note of the period table is a black key on a piano
+pub fn is_black_key(note: u32) -> bool{
+    NOTE_NAMES[((note + FIRST_MIDI_NOTE) % 12) as usize].ends_with('#')
+}
+
+/// The note of the period table a number of semitones above the C of an octave,
+/// or None if the table doesn't reach it
+pub fn octave_note(octave: u32, semitone: u32) -> Option<u32>{
+    ((octave + 1) * 12 + semitone).checked_sub(FIRST_MIDI_NOTE).filter(|note| *note < NOTE_COUNT as u32)
+}
+
+/// If a note of the period table is a C, the start of an octave
+pub fn is_octave_start(note: u32) -> bool{
+    (note + FIRST_MIDI_NOTE).is_multiple_of(12)
+}
+
+///
+/// Which channels are muted and soloed. The play thread reads it through an Arc
+/// on every sample, so changes are heard without restarting playback
+///
+#[derive(Debug, Default)]
+pub struct ChannelMix{
+    muted: [AtomicBool; 4],
+    soloed: [AtomicBool; 4],
+}
+
+impl ChannelMix{
+    pub fn is_muted(&self, channel: usize) -> bool{
+        self.muted[channel].load(Ordering::Relaxed)
+    }
+
+    pub fn set_muted(&self, channel: usize, muted: bool){
+        self.muted[channel].store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_soloed(&self, channel: usize) -> bool{
+        self.soloed[channel].load(Ordering::Relaxed)
+    }
+
+    pub fn set_soloed(&self, channel: usize, soloed: bool){
+        self.soloed[channel].store(soloed, Ordering::Relaxed);
+    }
+
+    /// If a channel can be heard. While any channel is soloed only soloed channels
+    /// are heard, and a muted channel is never heard
+    pub fn is_audible(&self, channel: usize) -> bool{
+        let any_soloed = (0 .. self.soloed.len()).any(|i| self.is_soloed(i));
+        !self.is_muted(channel) && (!any_soloed || self.is_soloed(channel))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Oscillators {
     pulse_one: NESPulseWave,
@@ -26,27 +98,39 @@ pub struct Oscillators {
     triangle: NESTriangleWave,
     noise: NESNoise,
     num_sample: usize,
-    beats_per_second: f32,
     length: usize,
 
+    clock: RowClock,
+    mix: Arc<ChannelMix>,
+
+    // Instruments are stepped once every engine frame
+    samples_per_frame: f32,
+    frame_position: f32,
+
     low_pass_filter: filters::LowPassFilter,
     high_pass_filter1: filters::HighPassFilter,
     high_pass_filter2: filters::HighPassFilter,
 }
 
 impl Oscillators {
-    /// The frequency of the sine.
+    /// The oscillators for every channel of a track. Channels that mix
+    /// says can't be heard keep playing silently, so they can be unmuted at any time
     #[inline]
-    pub fn new(track: &Track, tempo: f32) -> Oscillators {
+    pub fn new(track: &Track, instruments: &[Instrument], mix: Arc<ChannelMix>) -> Oscillators {
         Oscillators {
-            pulse_one: NESPulseWave::new(track.channels[0].to_vec()),
-            pulse_two: NESPulseWave::new(track.channels[1].to_vec()),
-            triangle: NESTriangleWave::new(track.channels[2].to_vec()),
-            noise: NESNoise::new(track.channels[3].to_vec()),
+            pulse_one: NESPulseWave::new(Voice::new(track.channel_columns(0), instruments)),
+            pulse_two: NESPulseWave::new(Voice::new(track.channel_columns(1), instruments)),
+            triangle: NESTriangleWave::new(Voice::new(track.channel_columns(2), instruments)),
+            noise: NESNoise::new(Voice::new(track.channel_columns(3), instruments)),
             num_sample: 0,
-            beats_per_second: tempo / 60.0,
             length: track.get_length(),
 
+            clock: RowClock::new(track),
+            mix,
+
+            samples_per_frame: track.region.samples_per_frame(),
+            frame_position: 0.0,
+
             low_pass_filter: filters::LowPassFilter::default(),
             high_pass_filter1: filters::HighPassFilter::default(),
             high_pass_filter2: filters::HighPassFilter::default(),
@@ -55,6 +139,107 @@ impl Oscillators {
     }
 }
 
+impl Oscillators {
+    ///
+    /// Plays the song up to the start of the next engine frame without making any sound,
+    /// and returns what each channel's registers should hold for that frame.
+    /// Returns None once the song has ended. Used to export songs as register writes
+    ///
+    pub fn next_frame(&mut self) -> Option<[ChannelFrame; 4]> {
+        let mut retriggered = [false; 4];
+        let mut started = false;
+
+        loop {
+            self.clock.tick();
+            let index = self.clock.row_index();
+            // A frame cut short by the end of the song is still played
+            if index >= self.length {
+                if started {
+                    break;
+                }
+                return None;
+            }
+            started = true;
+
+            self.frame_position += 1.0;
+            let new_frame = self.frame_position >= self.samples_per_frame;
+            if new_frame {
+                self.frame_position -= self.samples_per_frame;
+            }
+
+            retriggered[0] |= self.pulse_one.voice.update(index, new_frame);
+            retriggered[1] |= self.pulse_two.voice.update(index, new_frame);
+            retriggered[2] |= self.triangle.voice.update(index, new_frame);
+            retriggered[3] |= self.noise.voice.update(index, new_frame);
+
+            if new_frame {
+                break;
+            }
+        }
+
+        let mut frames = [
+            ChannelFrame::pulse(&self.pulse_one.voice),
+            ChannelFrame::pulse(&self.pulse_two.voice),
+            ChannelFrame::triangle(&self.triangle.voice),
+            ChannelFrame::noise(&self.noise.voice),
+        ];
+
+        for (channel, frame) in frames.iter_mut().enumerate() {
+            // Channels that can't be heard are exported as silent
+            if !self.mix.is_audible(channel) {
+                *frame = ChannelFrame::default();
+            }
+            frame.retrigger = retriggered[channel] && frame.period.is_some();
+        }
+
+        Some(frames)
+    }
+}
+
+///
+/// What one channel plays for an engine frame, as the values of its NES registers
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChannelFrame {
+    /// The 11 bit period, or the noise period from 0 to 15 on the noise channel.
+    /// None when the channel is silent
+    pub period: Option<u16>,
+    /// The volume from 0 to 15. The triangle is only ever on at 15 or off
+    pub volume: u8,
+    /// The duty cycle from 0 to 3, on the pulse channels
+    pub duty: u8,
+    /// If a new note started during the frame, which restarts the channel
+    pub retrigger: bool,
+}
+
+impl ChannelFrame {
+    fn pulse(voice: &Voice) -> ChannelFrame {
+        let volume = (voice.volume() * 15.0).round() as u8;
+        let duty = voice.sequence_value(SequenceKind::Duty).unwrap_or(2).clamp(0, 3) as u8;
+        match voice.period() {
+            Some(period) if volume > 0 => ChannelFrame { period: Some(period as u16), volume, duty, retrigger: false },
+            _ => ChannelFrame::default(),
+        }
+    }
+
+    /// The triangle has no volume control, so a volume of 0 silences it
+    fn triangle(voice: &Voice) -> ChannelFrame {
+        match voice.period() {
+            Some(period) if voice.volume() > 0.0 => ChannelFrame { period: Some(period as u16), volume: 15, ..ChannelFrame::default() },
+            _ => ChannelFrame::default(),
+        }
+    }
+
+    /// Higher notes use shorter noise periods, so they sound higher
+    fn noise(voice: &Voice) -> ChannelFrame {
+        let volume = (voice.volume() * 15.0).round() as u8;
+        match voice.note {
+            Some(note) if volume > 0 => ChannelFrame { period: Some(15 - note.rem_euclid(16) as u16), volume, ..ChannelFrame::default() },
+            _ => ChannelFrame::default(),
+        }
+    }
+}
+
 impl Iterator for Oscillators {
     type Item = f32;
 
@@ -66,18 +251,27 @@ impl Iterator for Oscillators {
     fn next(&mut self) -> Option<f32> {
         self.num_sample = self.num_sample.wrapping_add(1);
 
-        let mut index = self.num_sample as f32 / (48000.0 / self.beats_per_second);
-        if index > self.length as f32{
+        self.clock.tick();
+        let index = self.clock.row_index();
+        if index >= self.length {
             return Some(0.0);
         }
 
-        index = index.min(self.length as f32 - 1.0);
+        self.frame_position += 1.0;
+        let new_frame = self.frame_position >= self.samples_per_frame;
+        if new_frame {
+            self.frame_position -= self.samples_per_frame;
+        }
 
         // This is awful, don't do something like this.
-        let p1 = self.pulse_one.next(self.num_sample, index as usize);
-        let p2 = self.pulse_two.next(self.num_sample, index as usize);
-        let t = self.triangle.next(self.num_sample, index as usize);
-        let n = self.noise.next(index as usize);
+        let p1 = self.pulse_one.next(index, new_frame);
+        let p2 = self.pulse_two.next(index, new_frame);
+        let t = self.triangle.next(index, new_frame);
+        let n = self.noise.next(index, new_frame);
+
+        // Channels that can't be heard are left out of the mixer
+        let audible = |channel: usize, sample: f32| if self.mix.is_audible(channel) {sample} else {0.0};
+        let (p1, p2, t, n) = (audible(0, p1), audible(1, p2), audible(2, t), audible(3, n));
 
         // As the NES mixer isn't linear this equation emulated it
         // TODO add dmc (the 0.0 / 22638.0)
@@ -116,6 +310,195 @@ impl Source for Oscillators {
     }
 }
 
+///
+/// Follows the note events of one channel's columns, and keeps track of the
+/// note that should be sounding along with its instrument and effects
+///
+#[derive(Clone, Debug)]
+struct Voice {
+    sequence_columns: Vec<WaveColumn>,
+    row: Option<usize>,
+    note: Option<i32>,
+
+    instruments: Vec<Instrument>,
+    instrument: Option<usize>,
+    player: InstrumentPlayer,
+
+    effects: EffectState,
+    // A column held back by a note delay, and how many frames are left
+    delayed_row: Option<(usize, u8)>,
+}
+
+impl Voice {
+    fn new(sequence_columns: Vec<WaveColumn>, instruments: &[Instrument]) -> Voice {
+        Voice {
+            sequence_columns,
+            row: None,
+            note: None,
+
+            instruments: instruments.to_vec(),
+            instrument: None,
+            player: InstrumentPlayer::default(),
+
+            effects: EffectState::default(),
+            delayed_row: None,
+        }
+    }
+
+    /// Moves the voice to the column at index, stepping the instrument and
+    /// effects first if a new frame has started.
+    /// Returns true when a new note was started, so the oscillator can restart
+    fn update(&mut self, index: usize, new_frame: bool) -> bool {
+        let mut retrigger = false;
+
+        if new_frame {
+            retrigger = self.tick_frame();
+        }
+
+        if self.row == Some(index) {
+            return retrigger;
+        }
+        self.row = Some(index);
+
+        // A new column replaces any column that is still waiting on a delay
+        self.delayed_row = None;
+
+        match self.sequence_columns[index].get_effect() {
+            Some(Effect { command: EffectCommand::NoteDelay, param }) if param > 0 => {
+                self.delayed_row = Some((index, param));
+                retrigger
+            },
+            _ => self.play_column(index) || retrigger,
+        }
+    }
+
+    /// Moves the instrument and effects on by one frame, and plays a
+    /// delayed column once its delay is over
+    fn tick_frame(&mut self) -> bool {
+        let instruments = &self.instruments;
+        if let Some(instrument) = self.instrument.and_then(|i| instruments.get(i)) {
+            self.player.tick(instrument);
+        }
+
+        if self.effects.tick() {
+            self.note = None;
+        }
+
+        match self.delayed_row {
+            Some((index, 1)) => {
+                self.delayed_row = None;
+                self.play_column(index)
+            },
+            Some((index, frames)) => {
+                self.delayed_row = Some((index, frames - 1));
+                false
+            },
+            None => false,
+        }
+    }
+
+    /// Starts the effect and note event of a column
+    fn play_column(&mut self, index: usize) -> bool {
+        let col = self.sequence_columns[index].clone();
+
+        // The volume is set first, so a volume slide on the same column starts from it
+        if let Some(volume) = col.get_volume() {
+            self.effects.set_volume(volume);
+        }
+
+        if let Some(effect) = col.get_effect() {
+            self.effects.apply(effect);
+        }
+
+        let pitch = Some(col.get_index()).filter(|pitch| *pitch != -1);
+
+        match col.get_event() {
+            NoteEvent::On => {
+                // Portamento slides to the new note rather than restarting
+                if self.effects.is_portamento() && self.note.is_some() {
+                    self.change_pitch(pitch);
+                    return false;
+                }
+                self.start_note(pitch, col.get_instrument());
+                true
+            },
+            // A held note after silence has nothing to hold, so it starts a new note
+            NoteEvent::Hold => {
+                if self.note.is_none() {
+                    self.start_note(pitch, col.get_instrument());
+                    return true;
+                }
+                self.change_pitch(pitch);
+                false
+            },
+            // Released notes ring out through the empty columns after them
+            NoteEvent::Release => {
+                let instruments = &self.instruments;
+                match self.instrument.and_then(|i| instruments.get(i)) {
+                    Some(instrument) if instrument.has_release() => self.player.release(instrument),
+                    _ => self.note = None,
+                }
+                false
+            },
+            NoteEvent::Empty => {
+                if !self.player.is_released() {
+                    self.note = None;
+                }
+                false
+            },
+        }
+    }
+
+    fn start_note(&mut self, note: Option<i32>, instrument: Option<u8>) {
+        if let Some(new_note) = note {
+            self.effects.start_note(None, get_period(new_note));
+        }
+
+        self.note = note;
+        self.instrument = instrument.map(|i| i as usize);
+        self.player = InstrumentPlayer::default();
+
+        if let Some(instrument) = self.instrument.and_then(|i| self.instruments.get(i)) {
+            self.player.trigger(instrument);
+        }
+    }
+
+    /// Moves the sounding note to a new pitch without restarting it
+    fn change_pitch(&mut self, note: Option<i32>) {
+        if let Some(new_note) = note {
+            self.effects.start_note(self.note.map(get_period), get_period(new_note));
+        }
+        self.note = note;
+    }
+
+    fn get_instrument(&self) -> Option<&Instrument> {
+        self.instruments.get(self.instrument?)
+    }
+
+    /// The current value of one of the instrument's sequences
+    fn sequence_value(&self, kind: SequenceKind) -> Option<i8> {
+        self.player.value(self.get_instrument()?, kind)
+    }
+
+    /// The period of the sounding note, after the arpeggios, pitch sequences, and effects
+    fn period(&self) -> Option<i32> {
+        let note = self.note?
+            + self.sequence_value(SequenceKind::Arpeggio).unwrap_or(0) as i32
+            + self.effects.arpeggio_offset();
+
+        let period = get_period(note) + self.player.pitch_offset() + self.effects.period_offset();
+
+        // The NES's period registers are 11 bits
+        Some(period.clamp(0, 0x7FF))
+    }
+
+    /// The volume of the note from 0.0 to 1.0, from the volume sequence, the column
+    /// volume, and volume slides
+    fn volume(&self) -> f32 {
+        self.sequence_value(SequenceKind::Volume).unwrap_or(15) as f32 / 15.0 * self.effects.volume()
+    }
+}
+
 /// 
 /// Creates a triangle wave using 16 steps. This is a limitation of the NES and 
 /// what gives it a unique sound
@@ -123,16 +506,18 @@ impl Source for Oscillators {
 /// 
 #[derive(Clone, Debug)]
 pub struct NESTriangleWave {
-    sequence_columns: Vec<WaveColumn>,
+    voice: Voice,
+    phase: f32,
     steps: [f32;16],
 }
 
 impl NESTriangleWave {
     /// The frequency of the sine.
     #[inline]
-    pub fn new(sequence_columns: Vec<WaveColumn>) -> NESTriangleWave {
+    fn new(voice: Voice) -> NESTriangleWave {
         NESTriangleWave {
-            sequence_columns: sequence_columns,
+            voice,
+            phase: 0.0,
 
             // The steps of the triangle wave
             steps: [-1.0, -0.86666, -0.73333, -0.6, -0.46666, -0.33333, -0.2, -0.06666, 0.06666, 0.2, 0.33333, 0.46666, 0.6, 0.73333, 0.86666, 1.0],
@@ -141,25 +526,28 @@ impl NESTriangleWave {
 
     ///
     /// This function imitates the NES triangle wave
-    /// This could probably be generalized more
+    /// The triangle has no volume control, so a volume of 0 silences it
     /// TODO double check this is correct!
     /// 
     #[inline]
-    fn next(&mut self, num_sample: usize, index: usize) -> f32 {
-
-        let col = &self.sequence_columns[index as usize];
+    fn next(&mut self, index: usize, new_frame: bool) -> f32 {
 
-        if col.get_index() == -1 {
-            return 0.0;
+        if self.voice.update(index, new_frame) {
+            self.phase = 0.0;
         }
 
+        let period = match self.voice.period() {
+            Some(period) if self.voice.volume() > 0.0 => period,
+            _ => return 0.0,
+        };
+
         // The frequency of the triangle wave on the NES is 1 octave lower hence the / 2.0
-        let freq = get_frequency(col.get_index()) / 2.0;
+        let freq = get_period_frequency(period) / 2.0;
 
-        let freq_ratio = freq / 48000.0;
+        self.phase = (self.phase + freq / 48000.0) % 1.0;
 
         // Create a triangle wave, from 0-15 as float values
-        let mut x = ((((num_sample as f32 * 30.0) * freq_ratio) % 30.0) - 15.0).abs();
+        let mut x = ((self.phase * 30.0) - 15.0).abs();
 
         // Round the float values to indexes of an array corresponsing to the stepped triangle wave
         x = x.round();
@@ -174,40 +562,47 @@ impl NESTriangleWave {
 /// 
 #[derive(Clone, Debug)]
 pub struct NESPulseWave {
-    sequence_columns: Vec<WaveColumn>,
-    duty: f32,
+    voice: Voice,
+    phase: f32,
 }
 
+// The duty cycles of the NES pulse channels, chosen by the duty sequence
+const DUTY_CYCLES: [f32; 4] = [0.125, 0.25, 0.5, 0.75];
+
 impl NESPulseWave {
-    /// The frequency of the sine.
-    /// Duty is time of each pulse. 0.5 is a square wave
     #[inline]
-    pub fn new(sequence_columns: Vec<WaveColumn>) -> NESPulseWave {
+    fn new(voice: Voice) -> NESPulseWave {
         NESPulseWave {
-            sequence_columns: sequence_columns,
-            duty: 0.5,
+            voice,
+            phase: 0.0,
         }
     }
 
     #[inline]
-    fn next(&mut self, num_sample: usize, index: usize) -> f32 {
-
-        let col = &self.sequence_columns[index as usize];
+    fn next(&mut self, index: usize, new_frame: bool) -> f32 {
 
-        if col.get_index() == -1 {
-            return 0.0;
+        // A new note restarts the sequencer, like writing to the NES's high period register
+        if self.voice.update(index, new_frame) {
+            self.phase = 0.0;
         }
 
-        let freq = get_frequency(col.get_index());
+        let period = match self.voice.period() {
+            None => return 0.0,
+            Some(period) => period,
+        };
 
-        // Messy, should be cleaned up a bit
-        // Divide the 48000 into segments of self.freq, then checks if the current sample
-        // is less than half of that. If it is then return 0.0 otherwise return 1.0
-        if (num_sample as f32 % (48000.0 / freq)) < (48000.0 / freq) * self.duty {
+        // Duty is time of each pulse. 0.5 is a square wave
+        let duty = DUTY_CYCLES[self.voice.sequence_value(SequenceKind::Duty).unwrap_or(2).clamp(0, 3) as usize];
+        let freq = get_period_frequency(period);
+
+        // Move through one period of the wave, and output 0.0 for the duty
+        // portion of it, otherwise the volume
+        self.phase = (self.phase + freq / 48000.0) % 1.0;
+        if self.phase < duty {
             return 0.0;
         }
 
-        1.0
+        self.voice.volume()
     }
 }
 
@@ -218,15 +613,15 @@ impl NESPulseWave {
 /// 
 #[derive(Clone, Debug)]
 pub struct NESNoise {
-    sequence_columns: Vec<WaveColumn>,
+    voice: Voice,
     steps: [f32;16],
 }
 
 impl NESNoise {
     #[inline]
-    pub fn new(sequence_columns: Vec<WaveColumn>) -> NESNoise {
+    fn new(voice: Voice) -> NESNoise {
         NESNoise {
-            sequence_columns: sequence_columns,
+            voice,
             // The steps that the noise can produce
             steps: [-1.0, -0.86666, -0.73333, -0.6, -0.46666, -0.33333, -0.2, -0.06666, 0.06666, 0.2, 0.33333, 0.46666, 0.6, 0.73333, 0.86666, 1.0],
         }
@@ -236,16 +631,16 @@ impl NESNoise {
     /// This function imitates the NES Noise
     /// 
     #[inline]
-    fn next(&mut self, index: usize) -> f32 {
-        let col = &self.sequence_columns[index as usize];
+    fn next(&mut self, index: usize, new_frame: bool) -> f32 {
+        self.voice.update(index, new_frame);
 
-        if col.get_index() == -1 {
+        if self.voice.note.is_none() {
             return 0.0;
         }
 
         let x = rand::thread_rng().gen_range(0..self.steps.len());
 
-        self.steps[x]
+        self.steps[x] * self.voice.volume()
     }
 }
 
@@ -256,8 +651,19 @@ fn get_frequency(note: i32) -> f32{
         return 0.0;
     }
     
+    get_period_frequency(PERIODS[note as usize] as i32)
+}
+
+/// The period of a note in the period table. Notes past either end of the table
+/// use the closest note
+fn get_period(note: i32) -> i32{
+    PERIODS[note.clamp(0, PERIODS.len() as i32 - 1) as usize] as i32
+}
+
+/// Converts a value of the NES's period register into a frequency
+fn get_period_frequency(period: i32) -> f32{
     // 17789773 is the NES CPU clock rate for NTSC
-    (1789773 / (16 * (PERIODS[note as usize] + 1))) as f32
+    (1789773 / (16 * (period + 1))) as f32
 }
 
 /// 
@@ -465,4 +871,56 @@ impl Source for NESNoiseNote {
     fn total_duration(&self) -> Option<Duration> {
         None
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn column(row: u32, effect: Option<Effect>) -> WaveColumn{
+        let mut column = WaveColumn::default();
+        column.select(row);
+        column.set_effect(effect);
+        column
+    }
+
+    /// The note the voice plays on each frame of its first column
+    fn first_column_notes(column: WaveColumn, frames: usize) -> Vec<Option<i32>>{
+        let mut voice = Voice::new(vec![column, WaveColumn::default()], &[]);
+
+        (0 .. frames).map(|frame| {
+            voice.update(0, frame > 0);
+            voice.note
+        }).collect()
+    }
+
+    #[test]
+    fn note_delay_holds_back_the_column(){
+        let delayed = column(20, Some(Effect { command: EffectCommand::NoteDelay, param: 2 }));
+        assert_eq!(first_column_notes(delayed, 4), vec![None, None, Some(20), Some(20)]);
+
+        // G00 plays straight away
+        let undelayed = column(20, Some(Effect { command: EffectCommand::NoteDelay, param: 0 }));
+        assert_eq!(first_column_notes(undelayed, 2), vec![Some(20), Some(20)]);
+    }
+
+    #[test]
+    fn next_column_replaces_a_delayed_column(){
+        let delayed = column(20, Some(Effect { command: EffectCommand::NoteDelay, param: 3 }));
+        let mut voice = Voice::new(vec![delayed, column(30, None)], &[]);
+
+        voice.update(0, false);
+        voice.update(0, true);
+        voice.update(1, false);
+        assert_eq!(voice.note, Some(30));
+
+        voice.update(1, true);
+        voice.update(1, true);
+        assert_eq!(voice.note, Some(30));
+    }
+
+    #[test]
+    fn note_cut_silences_the_note(){
+        let cut = column(20, Some(Effect { command: EffectCommand::NoteCut, param: 2 }));
+        assert_eq!(first_column_notes(cut, 4), vec![Some(20), Some(20), None, None]);
+    }
+}